@@ -0,0 +1,199 @@
+mod envelope;
+pub mod pulse;
+mod triangle;
+mod noise;
+mod dmc;
+mod frame_counter;
+
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+pub struct Apu {
+    pulse1: pulse::Pulse,
+    pulse2: pulse::Pulse,
+    triangle: triangle::Triangle,
+    noise: noise::Noise,
+    dmc: dmc::Dmc,
+    frame_counter: frame_counter::FrameCounter,
+    // non-linear mixer lookup tables
+    pulse_table: Vec<f32>,
+    tnd_table: Vec<f32>,
+    // CPU cycles since reset; channel timers tick on every other one
+    cycle: u64,
+}
+
+fn build_pulse_table() -> Vec<f32> {
+    let mut table = vec![0.0; 31];
+    for n in 1..31 {
+        table[n] = 95.52 / (8128.0 / (n as f32) + 100.0);
+    }
+    return table;
+}
+
+// indexed by 3 * triangle + 2 * noise + dmc
+fn build_tnd_table() -> Vec<f32> {
+    let mut table = vec![0.0; 203];
+    for n in 1..203 {
+        table[n] = 163.67 / (24329.0 / (n as f32) + 100.0);
+    }
+    return table;
+}
+
+pub fn new_apu() -> Apu {
+    return Apu {
+        pulse1: pulse::new_pulse(true),
+        pulse2: pulse::new_pulse(false),
+        triangle: triangle::new_triangle(),
+        noise: noise::new_noise(),
+        dmc: dmc::new_dmc(),
+        frame_counter: frame_counter::new_frame_counter(),
+        pulse_table: build_pulse_table(),
+        tnd_table: build_tnd_table(),
+        cycle: 0,
+    };
+}
+
+pub fn reset(apu: &mut Apu) {
+    // reset acts like a write of 0 to $4015
+    write_io(apu, 0x4015, 0x00);
+    frame_counter::reset(&mut apu.frame_counter);
+    apu.cycle = 0;
+}
+
+pub fn read_io(apu: &mut Apu, addr: u16) -> u8 {
+    match addr {
+        0x4015 => {
+            // status
+            let mut status = 0;
+            if pulse::is_length_active(&apu.pulse1) {
+                status = status | 0x01;
+            }
+            if pulse::is_length_active(&apu.pulse2) {
+                status = status | 0x02;
+            }
+            if triangle::is_length_active(&apu.triangle) {
+                status = status | 0x04;
+            }
+            if noise::is_length_active(&apu.noise) {
+                status = status | 0x08;
+            }
+            if dmc::is_active(&apu.dmc) {
+                status = status | 0x10;
+            }
+            if frame_counter::is_irq(&apu.frame_counter) {
+                status = status | 0x40;
+            }
+            if dmc::is_irq(&apu.dmc) {
+                status = status | 0x80;
+            }
+            // reading acknowledges the frame interrupt (but not the DMC one)
+            frame_counter::acknowledge_irq(&mut apu.frame_counter);
+            return status;
+        }
+        _ => {
+        }
+    }
+    return 0;
+}
+
+pub fn write_io(apu: &mut Apu, addr: u16, value: u8) {
+    match addr {
+        0x4000..=0x4003 => {
+            // pulse 1
+            pulse::write_register(&mut apu.pulse1, addr - 0x4000, value);
+        }
+        0x4004..=0x4007 => {
+            // pulse 2
+            pulse::write_register(&mut apu.pulse2, addr - 0x4004, value);
+        }
+        0x4008..=0x400B => {
+            // triangle
+            triangle::write_register(&mut apu.triangle, addr - 0x4008, value);
+        }
+        0x400C..=0x400F => {
+            // noise
+            noise::write_register(&mut apu.noise, addr - 0x400C, value);
+        }
+        0x4010..=0x4013 => {
+            // dmc
+            dmc::write_register(&mut apu.dmc, addr - 0x4010, value);
+        }
+        0x4015 => {
+            // channel enable
+            pulse::set_enabled(&mut apu.pulse1, (value & 0x01) != 0);
+            pulse::set_enabled(&mut apu.pulse2, (value & 0x02) != 0);
+            triangle::set_enabled(&mut apu.triangle, (value & 0x04) != 0);
+            noise::set_enabled(&mut apu.noise, (value & 0x08) != 0);
+            dmc::set_enabled(&mut apu.dmc, (value & 0x10) != 0);
+        }
+        0x4017 => {
+            // frame counter
+            let odd_cycle = (apu.cycle & 1) == 1;
+            frame_counter::write_control(&mut apu.frame_counter, value, odd_cycle);
+        }
+        _ => {
+        }
+    }
+}
+
+fn clock_quarter_frame(apu: &mut Apu) {
+    pulse::clock_envelope(&mut apu.pulse1);
+    pulse::clock_envelope(&mut apu.pulse2);
+    triangle::clock_linear_counter(&mut apu.triangle);
+    noise::clock_envelope(&mut apu.noise);
+}
+
+fn clock_half_frame(apu: &mut Apu) {
+    pulse::clock_length_and_sweep(&mut apu.pulse1);
+    pulse::clock_length_and_sweep(&mut apu.pulse2);
+    triangle::clock_length(&mut apu.triangle);
+    noise::clock_length(&mut apu.noise);
+}
+
+// one CPU cycle
+pub fn run(apu: &mut Apu) {
+    if (apu.cycle & 1) == 1 {
+        pulse::clock_timer(&mut apu.pulse1);
+        pulse::clock_timer(&mut apu.pulse2);
+    }
+    triangle::clock_timer(&mut apu.triangle);
+    noise::clock_timer(&mut apu.noise);
+    dmc::clock_timer(&mut apu.dmc);
+
+    let clocks = frame_counter::clock(&mut apu.frame_counter);
+    if (clocks & frame_counter::CLOCK_QUARTER_FRAME) != 0 {
+        clock_quarter_frame(apu);
+    }
+    if (clocks & frame_counter::CLOCK_HALF_FRAME) != 0 {
+        clock_half_frame(apu);
+    }
+    apu.cycle += 1;
+}
+
+pub fn is_frame_irq(apu: &Apu) -> bool {
+    return frame_counter::is_irq(&apu.frame_counter);
+}
+
+pub fn is_dmc_irq(apu: &Apu) -> bool {
+    return dmc::is_irq(&apu.dmc);
+}
+
+// address of the next DMC sample byte, when the CPU has to fetch one
+pub fn dmc_fetch_address(apu: &Apu) -> Option<u16> {
+    return dmc::fetch_address(&apu.dmc);
+}
+
+pub fn dmc_fill(apu: &mut Apu, value: u8) {
+    dmc::fill_sample_buffer(&mut apu.dmc, value);
+}
+
+// current output level in 0.0..1.0
+pub fn output(apu: &Apu) -> f32 {
+    let pulse = pulse::output(&apu.pulse1) + pulse::output(&apu.pulse2);
+    let tnd = 3 * (triangle::output(&apu.triangle) as usize)
+        + 2 * (noise::output(&apu.noise) as usize)
+        + dmc::output(&apu.dmc) as usize;
+    return apu.pulse_table[pulse as usize] + apu.tnd_table[tnd];
+}
@@ -0,0 +1,150 @@
+// NTSC output rates in CPU cycles
+const RATE_TABLE: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+
+pub struct Dmc {
+    irq_enabled: bool,
+    irq_flag: bool,
+    looping: bool,
+    timer_period: u16,
+    timer: u16,
+    output_level: u8,
+    // memory reader
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    // output unit
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+pub fn new_dmc() -> Dmc {
+    return Dmc {
+        irq_enabled: false,
+        irq_flag: false,
+        looping: false,
+        timer_period: RATE_TABLE[0],
+        timer: 0,
+        output_level: 0,
+        sample_address: 0xC000,
+        sample_length: 1,
+        current_address: 0xC000,
+        bytes_remaining: 0,
+        sample_buffer: None,
+        shift_register: 0,
+        bits_remaining: 8,
+        silence: true,
+    };
+}
+
+// reg is the register offset 0-3 ($4010-$4013)
+pub fn write_register(dmc: &mut Dmc, reg: u16, value: u8) {
+    match reg {
+        0 => {
+            dmc.irq_enabled = (value & 0x80) != 0;
+            if !dmc.irq_enabled {
+                dmc.irq_flag = false;
+            }
+            dmc.looping = (value & 0x40) != 0;
+            dmc.timer_period = RATE_TABLE[(value & 0x0F) as usize];
+        }
+        1 => {
+            dmc.output_level = value & 0x7F;
+        }
+        2 => {
+            dmc.sample_address = 0xC000 | ((value as u16) << 6);
+        }
+        3 => {
+            dmc.sample_length = ((value as u16) << 4) | 1;
+        }
+        _ => {
+        }
+    }
+}
+
+fn restart(dmc: &mut Dmc) {
+    dmc.current_address = dmc.sample_address;
+    dmc.bytes_remaining = dmc.sample_length;
+}
+
+// $4015 bit 4; the write also acknowledges the DMC interrupt
+pub fn set_enabled(dmc: &mut Dmc, enabled: bool) {
+    dmc.irq_flag = false;
+    if !enabled {
+        dmc.bytes_remaining = 0;
+    } else if dmc.bytes_remaining == 0 {
+        restart(dmc);
+    }
+}
+
+pub fn is_active(dmc: &Dmc) -> bool {
+    return dmc.bytes_remaining > 0;
+}
+
+pub fn is_irq(dmc: &Dmc) -> bool {
+    return dmc.irq_flag;
+}
+
+// address the memory reader wants to fetch, if the sample buffer has run empty
+pub fn fetch_address(dmc: &Dmc) -> Option<u16> {
+    if dmc.sample_buffer.is_none() && dmc.bytes_remaining > 0 {
+        return Some(dmc.current_address);
+    }
+    return None;
+}
+
+pub fn fill_sample_buffer(dmc: &mut Dmc, value: u8) {
+    dmc.sample_buffer = Some(value);
+    // the address wraps from $FFFF to $8000
+    dmc.current_address = if dmc.current_address == 0xFFFF { 0x8000 } else { dmc.current_address + 1 };
+    dmc.bytes_remaining -= 1;
+    if dmc.bytes_remaining == 0 {
+        if dmc.looping {
+            restart(dmc);
+        } else if dmc.irq_enabled {
+            dmc.irq_flag = true;
+        }
+    }
+}
+
+// every CPU cycle
+pub fn clock_timer(dmc: &mut Dmc) {
+    if dmc.timer > 0 {
+        dmc.timer -= 1;
+        return;
+    }
+    dmc.timer = dmc.timer_period - 1;
+
+    if !dmc.silence {
+        if (dmc.shift_register & 1) != 0 {
+            if dmc.output_level <= 125 {
+                dmc.output_level += 2;
+            }
+        } else if dmc.output_level >= 2 {
+            dmc.output_level -= 2;
+        }
+    }
+    dmc.shift_register = dmc.shift_register >> 1;
+
+    dmc.bits_remaining -= 1;
+    if dmc.bits_remaining == 0 {
+        dmc.bits_remaining = 8;
+        match dmc.sample_buffer.take() {
+            Some(value) => {
+                dmc.silence = false;
+                dmc.shift_register = value;
+            }
+            None => {
+                dmc.silence = true;
+            }
+        }
+    }
+}
+
+pub fn output(dmc: &Dmc) -> u8 {
+    return dmc.output_level;
+}
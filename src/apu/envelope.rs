@@ -0,0 +1,58 @@
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant_volume: bool,
+    // constant volume, or the divider period when decaying
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+pub fn new_envelope() -> Envelope {
+    return Envelope {
+        start: false,
+        looping: false,
+        constant_volume: false,
+        volume: 0,
+        divider: 0,
+        decay: 0,
+    };
+}
+
+// bits 0-5 of $4000/$4004/$400C; the loop flag doubles as the length counter halt
+pub fn write_control(envelope: &mut Envelope, value: u8) {
+    envelope.looping = (value & 0x20) != 0;
+    envelope.constant_volume = (value & 0x10) != 0;
+    envelope.volume = value & 0x0F;
+}
+
+pub fn restart(envelope: &mut Envelope) {
+    envelope.start = true;
+}
+
+// quarter frame clock
+pub fn clock(envelope: &mut Envelope) {
+    if envelope.start {
+        envelope.start = false;
+        envelope.decay = 15;
+        envelope.divider = envelope.volume;
+        return;
+    }
+    if envelope.divider > 0 {
+        envelope.divider -= 1;
+        return;
+    }
+    envelope.divider = envelope.volume;
+    if envelope.decay > 0 {
+        envelope.decay -= 1;
+    } else if envelope.looping {
+        envelope.decay = 15;
+    }
+}
+
+pub fn output(envelope: &Envelope) -> u8 {
+    if envelope.constant_volume {
+        return envelope.volume;
+    }
+    return envelope.decay;
+}
@@ -0,0 +1,126 @@
+// sequencer steps in CPU cycles after the $4017 write takes effect (NTSC)
+const STEP1: u32 = 7457;
+const STEP2: u32 = 14913;
+const STEP3: u32 = 22371;
+const STEP4_IRQ: u32 = 29828;
+const STEP4: u32 = 29829;
+const FOUR_STEP_LENGTH: u32 = 29830;
+const STEP5: u32 = 37281;
+const FIVE_STEP_LENGTH: u32 = 37282;
+
+// clocks returned by clock()
+pub const CLOCK_QUARTER_FRAME: u8 = 0x01;
+pub const CLOCK_HALF_FRAME: u8 = 0x02;
+
+pub struct FrameCounter {
+    five_step: bool,
+    irq_inhibit: bool,
+    irq_flag: bool,
+    cycle: u32,
+    last_value: u8,
+    // a $4017 write restarts the sequencer only after 3 or 4 CPU cycles
+    pending_value: Option<u8>,
+    write_delay: u8,
+}
+
+pub fn new_frame_counter() -> FrameCounter {
+    return FrameCounter {
+        five_step: false,
+        irq_inhibit: false,
+        irq_flag: false,
+        cycle: 0,
+        last_value: 0,
+        pending_value: None,
+        write_delay: 0,
+    };
+}
+
+// odd_cycle: the write landed between two APU cycles
+pub fn write_control(frame_counter: &mut FrameCounter, value: u8, odd_cycle: bool) {
+    frame_counter.last_value = value;
+    frame_counter.irq_inhibit = (value & 0x40) != 0;
+    if frame_counter.irq_inhibit {
+        frame_counter.irq_flag = false;
+    }
+    frame_counter.pending_value = Some(value);
+    frame_counter.write_delay = if odd_cycle { 4 } else { 3 };
+}
+
+// reset behaves as if the last $4017 value was written again
+pub fn reset(frame_counter: &mut FrameCounter) {
+    let value = frame_counter.last_value;
+    frame_counter.irq_flag = false;
+    write_control(frame_counter, value, false);
+}
+
+pub fn is_irq(frame_counter: &FrameCounter) -> bool {
+    return frame_counter.irq_flag;
+}
+
+pub fn acknowledge_irq(frame_counter: &mut FrameCounter) {
+    frame_counter.irq_flag = false;
+}
+
+fn set_irq(frame_counter: &mut FrameCounter) {
+    if !frame_counter.irq_inhibit {
+        frame_counter.irq_flag = true;
+    }
+}
+
+// one CPU cycle; returns the quarter/half frame clocks to deliver to the channels
+pub fn clock(frame_counter: &mut FrameCounter) -> u8 {
+    let mut clocks = 0;
+    if let Some(value) = frame_counter.pending_value {
+        frame_counter.write_delay -= 1;
+        if frame_counter.write_delay == 0 {
+            frame_counter.pending_value = None;
+            frame_counter.five_step = (value & 0x80) != 0;
+            frame_counter.cycle = 0;
+            if frame_counter.five_step {
+                // entering 5-step mode clocks the units immediately
+                clocks = CLOCK_QUARTER_FRAME | CLOCK_HALF_FRAME;
+            }
+            return clocks;
+        }
+    }
+
+    frame_counter.cycle += 1;
+    if frame_counter.five_step {
+        match frame_counter.cycle {
+            STEP1 | STEP3 => {
+                clocks = CLOCK_QUARTER_FRAME;
+            }
+            STEP2 | STEP5 => {
+                clocks = CLOCK_QUARTER_FRAME | CLOCK_HALF_FRAME;
+            }
+            FIVE_STEP_LENGTH => {
+                frame_counter.cycle = 0;
+            }
+            _ => {
+            }
+        }
+    } else {
+        match frame_counter.cycle {
+            STEP1 | STEP3 => {
+                clocks = CLOCK_QUARTER_FRAME;
+            }
+            STEP2 => {
+                clocks = CLOCK_QUARTER_FRAME | CLOCK_HALF_FRAME;
+            }
+            STEP4_IRQ => {
+                set_irq(frame_counter);
+            }
+            STEP4 => {
+                clocks = CLOCK_QUARTER_FRAME | CLOCK_HALF_FRAME;
+                set_irq(frame_counter);
+            }
+            FOUR_STEP_LENGTH => {
+                set_irq(frame_counter);
+                frame_counter.cycle = 0;
+            }
+            _ => {
+            }
+        }
+    }
+    return clocks;
+}
@@ -0,0 +1,96 @@
+use super::envelope;
+use super::LENGTH_TABLE;
+
+// NTSC timer periods in CPU cycles
+const PERIOD_TABLE: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+
+pub struct Noise {
+    enabled: bool,
+    // short mode feeds back bit 6 instead of bit 1, giving a 93 step sequence
+    short_mode: bool,
+    shift_register: u16,
+    timer_period: u16,
+    timer: u16,
+    length_counter: u8,
+    length_halt: bool,
+    envelope: envelope::Envelope,
+}
+
+pub fn new_noise() -> Noise {
+    return Noise {
+        enabled: false,
+        short_mode: false,
+        shift_register: 1,
+        timer_period: PERIOD_TABLE[0],
+        timer: 0,
+        length_counter: 0,
+        length_halt: false,
+        envelope: envelope::new_envelope(),
+    };
+}
+
+// reg is the register offset 0-3 ($400C-$400F)
+pub fn write_register(noise: &mut Noise, reg: u16, value: u8) {
+    match reg {
+        0 => {
+            noise.length_halt = (value & 0x20) != 0;
+            envelope::write_control(&mut noise.envelope, value);
+        }
+        2 => {
+            noise.short_mode = (value & 0x80) != 0;
+            noise.timer_period = PERIOD_TABLE[(value & 0x0F) as usize];
+        }
+        3 => {
+            if noise.enabled {
+                noise.length_counter = LENGTH_TABLE[(value >> 3) as usize];
+            }
+            envelope::restart(&mut noise.envelope);
+        }
+        _ => {
+        }
+    }
+}
+
+pub fn set_enabled(noise: &mut Noise, enabled: bool) {
+    noise.enabled = enabled;
+    if !enabled {
+        noise.length_counter = 0;
+    }
+}
+
+pub fn is_length_active(noise: &Noise) -> bool {
+    return noise.length_counter > 0;
+}
+
+// every CPU cycle
+pub fn clock_timer(noise: &mut Noise) {
+    if noise.timer > 0 {
+        noise.timer -= 1;
+        return;
+    }
+    noise.timer = noise.timer_period - 1;
+    let tap = if noise.short_mode { 6 } else { 1 };
+    let feedback = (noise.shift_register ^ (noise.shift_register >> tap)) & 1;
+    noise.shift_register = (noise.shift_register >> 1) | (feedback << 14);
+}
+
+// quarter frame clock
+pub fn clock_envelope(noise: &mut Noise) {
+    envelope::clock(&mut noise.envelope);
+}
+
+// half frame clock
+pub fn clock_length(noise: &mut Noise) {
+    if !noise.length_halt && noise.length_counter > 0 {
+        noise.length_counter -= 1;
+    }
+}
+
+pub fn output(noise: &Noise) -> u8 {
+    if noise.length_counter == 0 || (noise.shift_register & 1) != 0 {
+        return 0;
+    }
+    return envelope::output(&noise.envelope);
+}
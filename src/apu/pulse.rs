@@ -0,0 +1,164 @@
+use super::envelope;
+use super::LENGTH_TABLE;
+
+// the sequencer counts down, so these read right to left
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1],
+    [0, 0, 0, 0, 0, 0, 1, 1],
+    [0, 0, 0, 0, 1, 1, 1, 1],
+    [1, 1, 1, 1, 1, 1, 0, 0],
+];
+
+pub struct Pulse {
+    enabled: bool,
+    // the sweep of pulse 1 negates with ones' complement, pulse 2 with two's complement
+    ones_complement: bool,
+    // expansion pulses (MMC5) have no sweep unit, so nothing mutes them
+    has_sweep: bool,
+    duty: u8,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    length_counter: u8,
+    length_halt: bool,
+    envelope: envelope::Envelope,
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_reload: bool,
+    sweep_divider: u8,
+}
+
+pub fn new_pulse(ones_complement: bool) -> Pulse {
+    return Pulse {
+        enabled: false,
+        ones_complement: ones_complement,
+        has_sweep: true,
+        duty: 0,
+        sequence_step: 0,
+        timer_period: 0,
+        timer: 0,
+        length_counter: 0,
+        length_halt: false,
+        envelope: envelope::new_envelope(),
+        sweep_enabled: false,
+        sweep_period: 0,
+        sweep_negate: false,
+        sweep_shift: 0,
+        sweep_reload: false,
+        sweep_divider: 0,
+    };
+}
+
+pub fn new_pulse_without_sweep() -> Pulse {
+    let mut pulse = new_pulse(false);
+    pulse.has_sweep = false;
+    return pulse;
+}
+
+// reg is the register offset 0-3 ($4000-$4003 or $4004-$4007)
+pub fn write_register(pulse: &mut Pulse, reg: u16, value: u8) {
+    match reg {
+        0 => {
+            pulse.duty = value >> 6;
+            pulse.length_halt = (value & 0x20) != 0;
+            envelope::write_control(&mut pulse.envelope, value);
+        }
+        1 => {
+            pulse.sweep_enabled = (value & 0x80) != 0;
+            pulse.sweep_period = (value >> 4) & 0x07;
+            pulse.sweep_negate = (value & 0x08) != 0;
+            pulse.sweep_shift = value & 0x07;
+            pulse.sweep_reload = true;
+        }
+        2 => {
+            pulse.timer_period = (pulse.timer_period & 0x0700) | (value as u16);
+        }
+        3 => {
+            pulse.timer_period = (pulse.timer_period & 0x00FF) | (((value & 0x07) as u16) << 8);
+            if pulse.enabled {
+                pulse.length_counter = LENGTH_TABLE[(value >> 3) as usize];
+            }
+            pulse.sequence_step = 0;
+            envelope::restart(&mut pulse.envelope);
+        }
+        _ => {
+        }
+    }
+}
+
+pub fn set_enabled(pulse: &mut Pulse, enabled: bool) {
+    pulse.enabled = enabled;
+    if !enabled {
+        pulse.length_counter = 0;
+    }
+}
+
+pub fn is_length_active(pulse: &Pulse) -> bool {
+    return pulse.length_counter > 0;
+}
+
+fn sweep_target(pulse: &Pulse) -> u16 {
+    let change = pulse.timer_period >> pulse.sweep_shift;
+    if !pulse.sweep_negate {
+        return pulse.timer_period + change;
+    }
+    if pulse.ones_complement {
+        return pulse.timer_period.saturating_sub(change + 1);
+    }
+    return pulse.timer_period.saturating_sub(change);
+}
+
+fn is_muted(pulse: &Pulse) -> bool {
+    if !pulse.has_sweep {
+        return false;
+    }
+    // the sweep unit mutes the channel even while disabled
+    return pulse.timer_period < 8 || sweep_target(pulse) > 0x07FF;
+}
+
+// every APU cycle (2 CPU cycles)
+pub fn clock_timer(pulse: &mut Pulse) {
+    if pulse.timer == 0 {
+        pulse.timer = pulse.timer_period;
+        pulse.sequence_step = (pulse.sequence_step + 7) & 0x07;
+    } else {
+        pulse.timer -= 1;
+    }
+}
+
+// quarter frame clock
+pub fn clock_envelope(pulse: &mut Pulse) {
+    envelope::clock(&mut pulse.envelope);
+}
+
+// half frame clock
+pub fn clock_length_and_sweep(pulse: &mut Pulse) {
+    if !pulse.length_halt && pulse.length_counter > 0 {
+        pulse.length_counter -= 1;
+    }
+
+    if !pulse.has_sweep {
+        return;
+    }
+    if pulse.sweep_divider == 0 && pulse.sweep_enabled && pulse.sweep_shift > 0 && !is_muted(pulse) {
+        pulse.timer_period = sweep_target(pulse);
+    }
+    if pulse.sweep_divider == 0 || pulse.sweep_reload {
+        pulse.sweep_divider = pulse.sweep_period;
+        pulse.sweep_reload = false;
+    } else {
+        pulse.sweep_divider -= 1;
+    }
+}
+
+pub fn output(pulse: &Pulse) -> u8 {
+    if pulse.length_counter == 0 || is_muted(pulse) {
+        return 0;
+    }
+    if DUTY_TABLE[pulse.duty as usize][pulse.sequence_step as usize] == 0 {
+        return 0;
+    }
+    return envelope::output(&pulse.envelope);
+}
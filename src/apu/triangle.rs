@@ -0,0 +1,102 @@
+use super::LENGTH_TABLE;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+pub struct Triangle {
+    enabled: bool,
+    // halts the length counter and keeps the linear counter reloading
+    control: bool,
+    sequence_step: u8,
+    timer_period: u16,
+    timer: u16,
+    length_counter: u8,
+    linear_counter: u8,
+    linear_reload_value: u8,
+    linear_reload: bool,
+}
+
+pub fn new_triangle() -> Triangle {
+    return Triangle {
+        enabled: false,
+        control: false,
+        sequence_step: 0,
+        timer_period: 0,
+        timer: 0,
+        length_counter: 0,
+        linear_counter: 0,
+        linear_reload_value: 0,
+        linear_reload: false,
+    };
+}
+
+// reg is the register offset 0-3 ($4008-$400B)
+pub fn write_register(triangle: &mut Triangle, reg: u16, value: u8) {
+    match reg {
+        0 => {
+            triangle.control = (value & 0x80) != 0;
+            triangle.linear_reload_value = value & 0x7F;
+        }
+        2 => {
+            triangle.timer_period = (triangle.timer_period & 0x0700) | (value as u16);
+        }
+        3 => {
+            triangle.timer_period = (triangle.timer_period & 0x00FF) | (((value & 0x07) as u16) << 8);
+            if triangle.enabled {
+                triangle.length_counter = LENGTH_TABLE[(value >> 3) as usize];
+            }
+            triangle.linear_reload = true;
+        }
+        _ => {
+        }
+    }
+}
+
+pub fn set_enabled(triangle: &mut Triangle, enabled: bool) {
+    triangle.enabled = enabled;
+    if !enabled {
+        triangle.length_counter = 0;
+    }
+}
+
+pub fn is_length_active(triangle: &Triangle) -> bool {
+    return triangle.length_counter > 0;
+}
+
+// every CPU cycle
+pub fn clock_timer(triangle: &mut Triangle) {
+    if triangle.timer == 0 {
+        triangle.timer = triangle.timer_period;
+        if triangle.length_counter > 0 && triangle.linear_counter > 0 {
+            triangle.sequence_step = (triangle.sequence_step + 1) & 0x1F;
+        }
+    } else {
+        triangle.timer -= 1;
+    }
+}
+
+// quarter frame clock
+pub fn clock_linear_counter(triangle: &mut Triangle) {
+    if triangle.linear_reload {
+        triangle.linear_counter = triangle.linear_reload_value;
+    } else if triangle.linear_counter > 0 {
+        triangle.linear_counter -= 1;
+    }
+    if !triangle.control {
+        triangle.linear_reload = false;
+    }
+}
+
+// half frame clock
+pub fn clock_length(triangle: &mut Triangle) {
+    if !triangle.control && triangle.length_counter > 0 {
+        triangle.length_counter -= 1;
+    }
+}
+
+pub fn output(triangle: &Triangle) -> u8 {
+    // a halted sequencer keeps outputting its current step
+    return SEQUENCE[triangle.sequence_step as usize];
+}
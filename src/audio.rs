@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+// NTSC CPU clock; the APU produces one sample per CPU cycle
+pub const CPU_CLOCK_RATE: f64 = 1789773.0;
+
+// largest rate adjustment applied by the dynamic rate control
+const MAX_RATE_DELTA: f64 = 0.005;
+
+// the NES output stage: two high-pass filters (90Hz, 440Hz) and a 14kHz low-pass
+const HIGH_PASS1_CUTOFF: f64 = 90.0;
+const HIGH_PASS2_CUTOFF: f64 = 440.0;
+const LOW_PASS_CUTOFF: f64 = 14000.0;
+
+// band-limited steps: each change in the input is drawn as a windowed-sinc step
+// KERNEL_WIDTH output samples long, interpolated from KERNEL_PHASES sub-sample offsets
+const KERNEL_WIDTH: usize = 32;
+const KERNEL_PHASES: usize = 64;
+// cutoff as a fraction of the output rate, leaving room for the window's transition band below Nyquist
+const KERNEL_CUTOFF: f64 = 0.42;
+
+struct HighPass {
+    alpha: f64,
+    previous_input: f64,
+    previous_output: f64,
+}
+
+fn new_high_pass(cutoff: f64, sample_rate: f64) -> HighPass {
+    let rc = 1.0 / (2.0 * PI * cutoff);
+    return HighPass {
+        alpha: rc / (rc + 1.0 / sample_rate),
+        previous_input: 0.0,
+        previous_output: 0.0,
+    };
+}
+
+struct LowPass {
+    alpha: f64,
+    previous_output: f64,
+}
+
+fn new_low_pass(cutoff: f64, sample_rate: f64) -> LowPass {
+    return LowPass {
+        alpha: 1.0 - (-2.0 * PI * cutoff / sample_rate).exp(),
+        previous_output: 0.0,
+    };
+}
+
+fn apply_low_pass(filter: &mut LowPass, input: f64) -> f64 {
+    filter.previous_output += (input - filter.previous_output) * filter.alpha;
+    return filter.previous_output;
+}
+
+fn apply_high_pass(filter: &mut HighPass, input: f64) -> f64 {
+    let output = filter.alpha * (filter.previous_output + input - filter.previous_input);
+    filter.previous_input = input;
+    filter.previous_output = output;
+    return output;
+}
+
+// band-limited impulse for each sub-sample offset; summed up they give a step without
+// content above the output Nyquist frequency, so nothing aliases back down
+fn build_kernels() -> Vec<[f64; KERNEL_WIDTH]> {
+    let mut kernels = Vec::with_capacity(KERNEL_PHASES + 1);
+    let center = (KERNEL_WIDTH / 2 - 1) as f64;
+    for phase in 0..KERNEL_PHASES + 1 {
+        let offset = phase as f64 / KERNEL_PHASES as f64;
+        let mut kernel = [0.0; KERNEL_WIDTH];
+        let mut sum = 0.0;
+        for tap in 0..KERNEL_WIDTH {
+            let x = tap as f64 - center - offset;
+            let sinc = if x == 0.0 { 1.0 } else { (2.0 * PI * KERNEL_CUTOFF * x).sin() / (2.0 * PI * KERNEL_CUTOFF * x) };
+            // Blackman window over the kernel width
+            let w = (x + KERNEL_WIDTH as f64 / 2.0) / KERNEL_WIDTH as f64;
+            let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+            kernel[tap] = sinc * window.max(0.0);
+            sum += kernel[tap];
+        }
+        // every step reaches exactly its full height
+        for tap in 0..KERNEL_WIDTH {
+            kernel[tap] /= sum;
+        }
+        kernels.push(kernel);
+    }
+    return kernels;
+}
+
+// band-limited step synthesis from the CPU rate down to the output rate
+pub struct Resampler {
+    ratio: f64,
+    adjusted_ratio: f64,
+    kernels: Vec<[f64; KERNEL_WIDTH]>,
+    // position of the current input sample, in output samples from deltas[0]
+    time: f64,
+    previous_input: f64,
+    // differences still being added to by steps; deltas[0] is the next output sample
+    deltas: VecDeque<f64>,
+    level: f64,
+    low_pass: LowPass,
+    high_pass1: HighPass,
+    high_pass2: HighPass,
+    output: Vec<f32>,
+}
+
+pub fn new_resampler(input_rate: f64, output_rate: f64) -> Resampler {
+    let ratio = input_rate / output_rate;
+    return Resampler {
+        ratio: ratio,
+        adjusted_ratio: ratio,
+        kernels: build_kernels(),
+        time: 0.0,
+        previous_input: 0.0,
+        deltas: vec![0.0; KERNEL_WIDTH].into_iter().collect(),
+        level: 0.0,
+        low_pass: new_low_pass(LOW_PASS_CUTOFF, output_rate),
+        high_pass1: new_high_pass(HIGH_PASS1_CUTOFF, output_rate),
+        high_pass2: new_high_pass(HIGH_PASS2_CUTOFF, output_rate),
+        output: Vec::new(),
+    };
+}
+
+// fill is the queued audio relative to the target: 0.0 empty, 1.0 on target, 2.0 twice
+// the target (anything above is clamped); below target the resampler emits up to
+// MAX_RATE_DELTA more samples per input sample, above it up to MAX_RATE_DELTA fewer
+pub fn set_fill_level(resampler: &mut Resampler, fill: f64) {
+    let fill = fill.max(0.0).min(2.0);
+    resampler.adjusted_ratio = resampler.ratio * (1.0 + MAX_RATE_DELTA * (fill - 1.0));
+}
+
+fn add_step(resampler: &mut Resampler, delta: f64) {
+    // interpolate between the two nearest phases
+    let position = resampler.time * KERNEL_PHASES as f64;
+    let phase = position as usize;
+    let fraction = position - phase as f64;
+    let before = &resampler.kernels[phase];
+    let after = &resampler.kernels[phase + 1];
+    for tap in 0..KERNEL_WIDTH {
+        resampler.deltas[tap] += delta * (before[tap] + (after[tap] - before[tap]) * fraction);
+    }
+}
+
+// an output sample is complete once no later step can reach it
+fn finish_sample(resampler: &mut Resampler) {
+    let delta = resampler.deltas.pop_front().unwrap();
+    resampler.deltas.push_back(0.0);
+    resampler.level += delta;
+
+    let mut output = apply_low_pass(&mut resampler.low_pass, resampler.level);
+    output = apply_high_pass(&mut resampler.high_pass1, output);
+    output = apply_high_pass(&mut resampler.high_pass2, output);
+    resampler.output.push(output as f32);
+}
+
+pub fn push_sample(resampler: &mut Resampler, sample: f32) {
+    let input = sample as f64;
+    if input != resampler.previous_input {
+        let delta = input - resampler.previous_input;
+        add_step(resampler, delta);
+        resampler.previous_input = input;
+    }
+
+    resampler.time += 1.0 / resampler.adjusted_ratio;
+    while resampler.time >= 1.0 {
+        resampler.time -= 1.0;
+        finish_sample(resampler);
+    }
+}
+
+pub fn take_samples(resampler: &mut Resampler) -> Vec<f32> {
+    let mut samples = Vec::with_capacity(resampler.output.len());
+    samples.append(&mut resampler.output);
+    return samples;
+}
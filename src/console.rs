@@ -0,0 +1,19 @@
+use super::cpu;
+use super::cpu_memory;
+use super::ppu;
+use super::apu;
+
+// hard power cycle: RAM is reinitialised according to `ram_init`
+pub fn power_on(cpu: &mut cpu::Cpu, mem: &mut cpu_memory::CpuMemory, ram_init: cpu_memory::RamInit) {
+    cpu_memory::power_on(mem, ram_init);
+    ppu::power_on(&mut mem.ppu);
+    mem.apu = apu::new_apu();
+    cpu::power_on(cpu, mem);
+}
+
+// soft reset (the console's reset button): RAM is kept
+pub fn reset(cpu: &mut cpu::Cpu, mem: &mut cpu_memory::CpuMemory) {
+    ppu::reset(&mut mem.ppu);
+    apu::reset(&mut mem.apu);
+    cpu::reset(cpu, mem);
+}
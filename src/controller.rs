@@ -0,0 +1,47 @@
+// standard controller buttons in report order
+pub const BUTTON_A: u8 = 0x01;
+pub const BUTTON_B: u8 = 0x02;
+pub const BUTTON_SELECT: u8 = 0x04;
+pub const BUTTON_START: u8 = 0x08;
+pub const BUTTON_UP: u8 = 0x10;
+pub const BUTTON_DOWN: u8 = 0x20;
+pub const BUTTON_LEFT: u8 = 0x40;
+pub const BUTTON_RIGHT: u8 = 0x80;
+
+pub struct Controller {
+    buttons: u8,
+    shift: u8,
+    strobe: bool,
+}
+
+pub fn new_controller() -> Controller {
+    return Controller {
+        buttons: 0,
+        shift: 0,
+        strobe: false,
+    };
+}
+
+pub fn set_buttons(controller: &mut Controller, buttons: u8) {
+    controller.buttons = buttons;
+}
+
+// $4016 bit 0 drives the strobe of both controllers
+pub fn write_strobe(controller: &mut Controller, value: u8) {
+    controller.strobe = (value & 0x01) != 0;
+    if controller.strobe {
+        controller.shift = controller.buttons;
+    }
+}
+
+// returns the serial data bit; after 8 reads an official controller reports 1
+pub fn read(controller: &mut Controller) -> u8 {
+    if controller.strobe {
+        // while strobe is high the shift register keeps reloading, so A is reported
+        controller.shift = controller.buttons;
+        return controller.shift & 0x01;
+    }
+    let bit = controller.shift & 0x01;
+    controller.shift = (controller.shift >> 1) | 0x80;
+    return bit;
+}
@@ -1,5 +1,8 @@
 use super::rom;
 use super::cpu_memory;
+use super::ppu;
+use super::apu;
+use super::vgm;
 use log::{info, trace, warn};
 
 mod opcode;
@@ -12,6 +15,10 @@ pub struct Cpu {
     pub reg_p: u8,
     pub reg_pc: u16,
     pub cycle: i16,
+    pub nmi_line: bool,
+    pub nmi_pending: bool,
+    pub irq_inhibit: bool,
+    pub interrupt_vector: u16,
 }
 
 pub fn new_cpu() -> Cpu {
@@ -21,8 +28,12 @@ pub fn new_cpu() -> Cpu {
         reg_y: 0,
         reg_s: 0xFD,
         reg_p: REG_P_FLAG_I | REG_P_FLAG_R,
-        reg_pc: 0x8000,
+        reg_pc: 0,
         cycle: 0,
+        nmi_line: false,
+        nmi_pending: false,
+        irq_inhibit: true,
+        interrupt_vector: 0,
     };
 }
 
@@ -44,9 +55,33 @@ const REG_P_FLAG_I: u8 = 0x04;
 const REG_P_FLAG_Z: u8 = 0x02;
 const REG_P_FLAG_C: u8 = 0x01;
 
+const VECTOR_NMI: u16 = 0xFFFA;
+const VECTOR_RESET: u16 = 0xFFFC;
+const VECTOR_IRQ: u16 = 0xFFFE;
+
+const INTERRUPT_CYCLES: i16 = 7;
+
+// cycles the CPU is halted while the DMC reads a sample byte
+const DMC_STALL_CYCLES: i16 = 4;
+
+pub fn power_on(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) {
+    cpu.reg_a = 0;
+    cpu.reg_x = 0;
+    cpu.reg_y = 0;
+    cpu.reg_s = 0;
+    cpu.reg_p = REG_P_FLAG_I | REG_P_FLAG_R;
+    reset(cpu, mem);
+}
+
 pub fn reset(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) {
-    cpu.reg_pc = cpu_memory::read_mem_word(mem, 0xFFFC);
+    // reset runs the interrupt sequence with writes suppressed: S drops by 3, nothing is stored
+    cpu.reg_s = cpu.reg_s.wrapping_sub(3);
     cpu.reg_p = cpu.reg_p | REG_P_FLAG_I;
+    cpu.reg_pc = cpu_memory::read_mem_word(mem, VECTOR_RESET);
+    cpu.nmi_pending = false;
+    cpu.irq_inhibit = true;
+    cpu.interrupt_vector = 0;
+    cpu.cycle = INTERRUPT_CYCLES;
 }
 
 fn fetch_pc_byte(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) -> u8 {
@@ -70,6 +105,13 @@ fn write_word(data: &mut Vec<u8>, p: u16, v: u16) {
     data[(p + 1) as usize] = ((v & 0xFF00) >> 8) as u8;
 }
 
+// read-modify-write instructions write the unmodified value back before the result
+fn read_modify(mem: &mut cpu_memory::CpuMemory, addr: u16) -> u8 {
+    let value = cpu_memory::read_mem(mem, addr);
+    cpu_memory::write_mem(mem, addr, value);
+    return value;
+}
+
 fn stack_push_byte(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, data: u8) {
     let stack_addr = 0x0100 | (cpu.reg_s as u16);
     // println!("stack push byte p={:04X} v={:04X}", stack_addr, data);
@@ -108,13 +150,69 @@ fn stack_pop_word(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) -> u16 {
     return data;
 }
 
+fn poll_nmi(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) {
+    // NMI is edge triggered: only a rising edge of the PPU output requests it,
+    // so toggling $2000 bit 7 during vblank can raise another one.
+    let line = ppu::is_nmi_asserted(&mem.ppu);
+    if line && !cpu.nmi_line {
+        cpu.nmi_pending = true;
+    }
+    cpu.nmi_line = line;
+}
+
+fn interrupt(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, vector: u16) {
+    let reg_pc = cpu.reg_pc;
+    stack_push_word(cpu, mem, reg_pc);
+    stack_push_byte(cpu, mem, (cpu.reg_p & REG_P_MASK_B) | REG_P_FLAG_R);
+    cpu.reg_p = cpu.reg_p | REG_P_FLAG_I;
+    cpu.irq_inhibit = true;
+    // the vector is fetched at the end of the sequence (see fetch_interrupt_vector)
+    cpu.interrupt_vector = vector;
+    cpu.cycle = INTERRUPT_CYCLES;
+}
+
+fn fetch_interrupt_vector(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) {
+    let mut vector = cpu.interrupt_vector;
+    cpu.interrupt_vector = 0;
+    // an NMI that arrives while BRK or IRQ is pushing hijacks the vector fetch
+    if vector == VECTOR_IRQ && cpu.nmi_pending {
+        cpu.nmi_pending = false;
+        vector = VECTOR_NMI;
+    }
+    cpu.reg_pc = cpu_memory::read_mem_word(mem, vector);
+}
+
 pub fn run(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) {
+    mem.cycles += 1;
+    poll_nmi(cpu, mem);
+
+    if let Some(addr) = apu::dmc_fetch_address(&mem.apu) {
+        let value = cpu_memory::read_mem(mem, addr);
+        apu::dmc_fill(&mut mem.apu, value);
+        if let Some(ref mut log) = mem.vgm_log {
+            vgm::log_sample_fetch(log, addr, value);
+        }
+        cpu.cycle += DMC_STALL_CYCLES;
+    }
+
     cpu.cycle = cpu.cycle - 1;
     if cpu.cycle > 0 {
         return;
     }
     cpu.reg_p = cpu.reg_p | REG_P_FLAG_R;
 
+    if cpu.interrupt_vector != 0 {
+        // the first handler instruction always runs before the next interrupt
+        fetch_interrupt_vector(cpu, mem);
+    } else if cpu.nmi_pending {
+        cpu.nmi_pending = false;
+        interrupt(cpu, mem, VECTOR_NMI);
+        return;
+    } else if !cpu.irq_inhibit && cpu_memory::is_irq_asserted(mem) {
+        interrupt(cpu, mem, VECTOR_IRQ);
+        return;
+    }
+
     // println!("pc: {:04X}", cpu.reg_pc);
 
     let pc = cpu.reg_pc;
@@ -122,11 +220,30 @@ pub fn run(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory) {
     let op = &opcode::OPCODE_TABLE[code as usize];
 
     // println!("{:04X}  {}                       A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", pc, opcode::OPCODE_DEBUG_SYMBOL[code as usize], cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.reg_p, cpu.reg_s);
-    println!("{:04X} {:02X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", pc, code, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.reg_p, cpu.reg_s);
+    trace!("{:04X} {:02X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}", pc, code, cpu.reg_a, cpu.reg_x, cpu.reg_y, cpu.reg_p, cpu.reg_s);
 
     // opcode::debug_opcode(code);
+    let reg_p = cpu.reg_p;
     exec_instructions(cpu, mem, op);
     cpu.cycle = op.cycles as i16;
+
+    if let Some(page) = mem.oam_dma_page.take() {
+        // the CPU halts for one cycle, one more to align to a read cycle, then 256 read/write pairs
+        cpu_memory::oam_dma(mem, page);
+        let start = mem.cycles + op.cycles as u64;
+        cpu.cycle += 513 + (start & 1) as i16;
+    }
+
+    // IRQ is polled before the last cycle of an instruction, so CLI, SEI and PLP
+    // only change whether it is taken after the following instruction
+    match op.code {
+        opcode::OPCODE_CLI | opcode::OPCODE_SEI | opcode::OPCODE_PLP => {
+            cpu.irq_inhibit = (reg_p & REG_P_FLAG_I) != 0;
+        }
+        _ => {
+            cpu.irq_inhibit = (cpu.reg_p & REG_P_FLAG_I) != 0;
+        }
+    }
 }
 
 fn read_by_addressing(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode::Opcode) -> u16 {
@@ -286,7 +403,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             let remain: u8;
             if op.addressing != opcode::ADDRESSING_ACCUMULATOR {
                 let addr = data as u16;
-                shift = cpu_memory::read_mem(mem, addr) as u8;
+                shift = read_modify(mem, addr);
                 remain = (shift & REG_P_FLAG_N) >> 7;
                 shift = shift << 1;
                 cpu_memory::write_mem(mem, addr, shift);
@@ -325,13 +442,13 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             cpu.reg_p = (cpu.reg_p & (REG_P_MASK_N & REG_P_MASK_Z & REG_P_MASK_C)) | (result & REG_P_FLAG_N) | (if result == 0 { REG_P_FLAG_Z } else { 0 }) | (if cpu.reg_y < (data as u8) { 0 } else { 1 });
         }
         opcode::OPCODE_INC => {
-            let mut value = cpu_memory::read_mem(mem, data as u16) as u8;
+            let mut value = read_modify(mem, data);
             value = value.wrapping_add(1);
             cpu_memory::write_mem(mem, data, value);
             cpu.reg_p = (cpu.reg_p & (REG_P_MASK_N & REG_P_MASK_Z)) | (value & REG_P_FLAG_N) | (if value == 0 { REG_P_FLAG_Z } else { 0 });
         }
         opcode::OPCODE_DEC => {
-            let mut value = cpu_memory::read_mem(mem, data as u16) as u8;
+            let mut value = read_modify(mem, data);
             value = value.wrapping_sub(1);
             cpu_memory::write_mem(mem, data, value);
             cpu.reg_p = (cpu.reg_p & (REG_P_MASK_N & REG_P_MASK_Z)) | (value & REG_P_FLAG_N) | (if value == 0 { REG_P_FLAG_Z } else { 0 });
@@ -360,7 +477,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             cpu.reg_p = (cpu.reg_p & (REG_P_MASK_N & REG_P_MASK_Z)) | (cpu.reg_y & REG_P_FLAG_N) | (if cpu.reg_y == 0 { REG_P_FLAG_Z } else { 0 });
         }
         opcode::OPCODE_ISC => {
-            let mut value = cpu_memory::read_mem(mem, data as u16) as u8;
+            let mut value = read_modify(mem, data);
             value = value.wrapping_add(1);
             cpu_memory::write_mem(mem, data, value);
             let result = (cpu.reg_a as i16).wrapping_sub(value as i16).wrapping_sub(1 - (cpu.reg_p & REG_P_FLAG_C) as i16);
@@ -373,7 +490,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             let remain: u8;
             if op.addressing != opcode::ADDRESSING_ACCUMULATOR {
                 let addr = data as u16;
-                shift = cpu_memory::read_mem(mem, addr) as u8;
+                shift = read_modify(mem, addr);
                 remain = shift & 1;
                 shift = shift >> 1;
                 cpu_memory::write_mem(mem, addr, shift);
@@ -397,7 +514,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             let remain: u8;
             if op.addressing != opcode::ADDRESSING_ACCUMULATOR {
                 let addr = data as u16;
-                shift = cpu_memory::read_mem(mem, addr) as u8;
+                shift = read_modify(mem, addr);
                 remain = (shift & 0x80) >> 7;
                 shift = shift << 1 | (cpu.reg_p & REG_P_FLAG_C);
                 cpu_memory::write_mem(mem, addr, shift);
@@ -414,7 +531,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             let remain: u8;
             if op.addressing != opcode::ADDRESSING_ACCUMULATOR {
                 let addr = data as u16;
-                shift = cpu_memory::read_mem(mem, addr) as u8;
+                shift = read_modify(mem, addr);
                 remain = shift & 1;
                 shift = shift >> 1 | ((cpu.reg_p & REG_P_FLAG_C) << 7);
                 cpu_memory::write_mem(mem, addr, shift);
@@ -513,20 +630,22 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
             cpu.reg_p = cpu.reg_p & REG_P_MASK_C;
         }
         opcode::OPCODE_BRK => {
-            stack_push_word(cpu, mem, cpu.reg_pc);
-            stack_push_byte(cpu, mem, cpu.reg_p);
-            cpu.reg_pc = cpu_memory::read_mem_word(mem, 0xFFFE);
-            cpu.reg_p = cpu.reg_p | REG_P_FLAG_B;
+            // BRK skips the padding byte after the opcode
+            let reg_pc = cpu.reg_pc.wrapping_add(1);
+            stack_push_word(cpu, mem, reg_pc);
+            stack_push_byte(cpu, mem, cpu.reg_p | REG_P_FLAG_B | REG_P_FLAG_R);
+            cpu.reg_p = cpu.reg_p | REG_P_FLAG_I;
+            cpu.interrupt_vector = VECTOR_IRQ;
         }
         opcode::OPCODE_RTI => {
-            cpu.reg_p = stack_pop_byte(cpu, mem);
+            cpu.reg_p = (stack_pop_byte(cpu, mem) & REG_P_MASK_B) | REG_P_FLAG_R;
             cpu.reg_pc = stack_pop_word(cpu, mem);
         }
         opcode::OPCODE_SAX => {
             cpu_memory::write_mem(mem, data, cpu.reg_a & cpu.reg_x);
         }
         opcode::OPCODE_DCP => {
-            let mut value = cpu_memory::read_mem(mem, data as u16) as u8;
+            let mut value = read_modify(mem, data);
             value = value.wrapping_sub(1);
             cpu_memory::write_mem(mem, data, value);
             let result = cpu.reg_a.wrapping_sub(value as u8);
@@ -534,7 +653,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
         }
         opcode::OPCODE_SLO => {
             let addr = data as u16;
-            data = cpu_memory::read_mem(mem, addr) as u16;
+            data = read_modify(mem, addr) as u16;
             let original = data as u8;
             let remain = (original & REG_P_FLAG_N) >> 7;
             let shift = original << 1;
@@ -544,7 +663,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
         }
         opcode::OPCODE_RLA => {
             let addr = data as u16;
-            data = cpu_memory::read_mem(mem, addr) as u16;
+            data = read_modify(mem, addr) as u16;
             let original = data as u8;
             let remain = (original & 0x80) >> 7;
             let shift = original << 1 | (cpu.reg_p & REG_P_FLAG_C);
@@ -554,7 +673,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
         }
         opcode::OPCODE_SRE => {
             let addr = data as u16;
-            data = cpu_memory::read_mem(mem, addr) as u16;
+            data = read_modify(mem, addr) as u16;
             let original = data as u8;
             let remain = original & 1;
             let shift = original >> 1;
@@ -564,7 +683,7 @@ fn exec_instructions(cpu: &mut Cpu, mem: &mut cpu_memory::CpuMemory, op: &opcode
         }
         opcode::OPCODE_RRA => {
             let addr = data as u16;
-            data = cpu_memory::read_mem(mem, addr) as u16;
+            data = read_modify(mem, addr) as u16;
             let original = data as u8;
             let remain = original & 1;
             let shift = original >> 1 | ((cpu.reg_p & REG_P_FLAG_C) << 7);
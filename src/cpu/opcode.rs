@@ -99,7 +99,7 @@ pub const ADDRESSING_INDIRECT: u8 = 13;
 
 pub const OPCODE_TABLE: [Opcode; 256] = [
     // 0x00
-    Opcode { code: OPCODE_BRK, bytes:0, cycles:0, addressing: ADDRESSING_IMPLIED},
+    Opcode { code: OPCODE_BRK, bytes:2, cycles:7, addressing: ADDRESSING_IMPLIED},
     Opcode { code: OPCODE_ORA, bytes:2, cycles:6, addressing: ADDRESSING_INDIRECT_X },
     Opcode { code: OPCODE_KIL, bytes:0, cycles:0,addressing: ADDRESSING_IMPLIED},
     Opcode { code: OPCODE_SLO, bytes:2, cycles:8, addressing: ADDRESSING_INDIRECT_X },
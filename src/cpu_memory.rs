@@ -1,23 +1,115 @@
+use std::time::{SystemTime, UNIX_EPOCH};
 use super::ppu;
+use super::apu;
+use super::vgm;
+use super::controller;
+use super::mapper;
+
+// sources sharing the level triggered IRQ line
+pub const IRQ_SOURCE_MAPPER: u8 = 0x01;
+pub const IRQ_SOURCE_FRAME_COUNTER: u8 = 0x02;
+pub const IRQ_SOURCE_DMC: u8 = 0x04;
+
+// how work RAM is filled on a power cycle
+#[derive(Clone, Copy, Debug)]
+pub enum RamInit {
+    Zero,
+    Fill(u8),
+    Random,
+}
+
+pub fn parse_ram_init(name: &str) -> Option<RamInit> {
+    match name {
+        "zero" => Some(RamInit::Zero),
+        "ff" => Some(RamInit::Fill(0xFF)),
+        "random" => Some(RamInit::Random),
+        _ => None,
+    }
+}
 
 pub struct CpuMemory<'a> {
     pub wram: Vec<u8>,
-    pub ext_ram: Vec<u8>,
-    pub backup_ram: Vec<u8>,
-    pub program_rom: Vec<u8>,
+    // $4020-$FFFF belongs to the cartridge
+    pub mapper: mapper::SharedMapper,
     pub ppu: &'a mut ppu::Ppu,
+    pub apu: apu::Apu,
+    pub controllers: [controller::Controller; 2],
+    pub irq: u8,
+    // CPU cycles since power on
+    pub cycles: u64,
+    // page written to $4014, copied to OAM once the writing instruction completes
+    pub oam_dma_page: Option<u8>,
+    // APU register writes are recorded here while a VGM capture runs
+    pub vgm_log: Option<vgm::VgmLog>,
+    // last value seen on the data bus; undriven bits of some registers read back from it
+    pub open_bus: u8,
 }
 
-pub fn new_memory<'a>(rom_data: &Vec<u8>, ppu: &'a mut ppu::Ppu) -> CpuMemory<'a> {
+pub fn new_memory<'a>(mapper: mapper::SharedMapper, ppu: &'a mut ppu::Ppu) -> CpuMemory<'a> {
     return CpuMemory {
         wram: vec![0; 0x0800],
-        ext_ram: vec![0; 0x1FE0],
-        backup_ram: vec![0; 0x2000],
-        program_rom: rom_data.clone(),
+        mapper: mapper,
         ppu: ppu,
+        apu: apu::new_apu(),
+        controllers: [controller::new_controller(), controller::new_controller()],
+        irq: 0,
+        cycles: 0,
+        oam_dma_page: None,
+        vgm_log: None,
+        open_bus: 0,
     };
 }
 
+pub fn power_on(mem: &mut CpuMemory, ram_init: RamInit) {
+    match ram_init {
+        RamInit::Zero => {
+            for v in mem.wram.iter_mut() {
+                *v = 0;
+            }
+        }
+        RamInit::Fill(value) => {
+            for v in mem.wram.iter_mut() {
+                *v = value;
+            }
+        }
+        RamInit::Random => {
+            // xorshift32 seeded from the clock; quality is irrelevant here
+            let mut seed = match SystemTime::now().duration_since(UNIX_EPOCH) {
+                Ok(time) => time.subsec_nanos() | 1,
+                Err(_) => 0x2545F491,
+            };
+            for v in mem.wram.iter_mut() {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                *v = seed as u8;
+            }
+        }
+    }
+    mem.irq = 0;
+    mem.oam_dma_page = None;
+}
+
+pub fn assert_irq(mem: &mut CpuMemory, source: u8) {
+    mem.irq = mem.irq | source;
+}
+
+pub fn release_irq(mem: &mut CpuMemory, source: u8) {
+    mem.irq = mem.irq & !source;
+}
+
+pub fn set_irq(mem: &mut CpuMemory, source: u8, asserted: bool) {
+    if asserted {
+        assert_irq(mem, source);
+    } else {
+        release_irq(mem, source);
+    }
+}
+
+pub fn is_irq_asserted(mem: &CpuMemory) -> bool {
+    return mem.irq != 0;
+}
+
 pub fn read_mem_word(mem: &mut CpuMemory, addr: u16) -> u16 {
     let data1 = read_mem(mem, addr) as u16;
     let data2 = read_mem(mem, addr + 1) as u16;
@@ -40,26 +132,24 @@ pub fn read_mem(mem: &mut CpuMemory, addr: u16) -> u8 {
         value = ppu::read_io(&mut mem.ppu, addr);
     } else if addr < 0x4000 {
         // unused
+    } else if addr == 0x4015 {
+        // apu status
+        value = apu::read_io(&mut mem.apu, addr);
+    } else if addr == 0x4016 || addr == 0x4017 {
+        // controllers; only bit 0 is driven, the upper bits are open bus
+        let bit = controller::read(&mut mem.controllers[(addr - 0x4016) as usize]);
+        value = (mem.open_bus & 0xE0) | bit;
     } else if addr < 0x4020 {
         // io
-    } else if addr < 0x6000 {
-        // ext ram
-        value = mem.ext_ram[(addr - 0x4020) as usize];
-    } else if addr < 0x8000 {
-        // backup rom
-        value = mem.backup_ram[(addr - 0x6000) as usize];
-    } else if addr <= 0xC000 {
-        // program rom
-        value = mem.program_rom[(addr - 0x8000) as usize];
     } else {
-        // program rom
-        if mem.program_rom.len() == 0x4000 {
-            value = mem.program_rom[(addr - 0xC000) as usize];
-        } else {
-            value = mem.program_rom[(addr - 0x8000) as usize];
-        }
+        // cartridge
+        value = match mem.mapper.borrow_mut().read_prg(addr) {
+            Some(value) => value,
+            None => mem.open_bus,
+        };
     }
     // println!("read {:04X?} value:{:02X}", addr, value);
+    mem.open_bus = value;
     return value;
 }
 
@@ -69,19 +159,56 @@ pub fn write_mem(mem: &mut CpuMemory, addr: u16, value: u8) {
         mem.wram[addr as usize] = value;
     } else if addr < 0x2000 {
         // unused
-    } else if addr < 0x2008 || addr == 0x4014 {
+    } else if addr == 0x4014 {
+        // oam dma
+        mem.oam_dma_page = Some(value);
+    } else if addr < 0x2008 {
         // ppu
         ppu::write_io(&mut mem.ppu, addr, value);
     } else if addr < 0x4000 {
         // unused
+    } else if addr < 0x4016 || addr == 0x4017 {
+        // apu
+        apu::write_io(&mut mem.apu, addr, value);
+        if let Some(ref mut log) = mem.vgm_log {
+            vgm::log_write(log, mem.cycles, addr, value);
+        }
+    } else if addr == 0x4016 {
+        // controller strobe
+        controller::write_strobe(&mut mem.controllers[0], value);
+        controller::write_strobe(&mut mem.controllers[1], value);
     } else if addr < 0x4020 {
-    } else if addr < 0x6000 {
-        mem.ext_ram[(addr - 0x4020) as usize] = value;
-    } else if addr < 0x8000 {
-        mem.backup_ram[(addr - 0x6000) as usize] = value;
-    } else if addr < 0x8000 {
     } else {
-        // program rom
-        // read only
+        // cartridge
+        mem.mapper.borrow_mut().write_prg(addr, value);
     }
-}
\ No newline at end of file
+}
+
+pub fn oam_dma(mem: &mut CpuMemory, page: u8) {
+    let base = (page as u16) << 8;
+    for i in 0..256 {
+        let value = read_mem(mem, base + i);
+        ppu::write_io(&mut mem.ppu, 0x2004, value);
+    }
+}
+
+// clocks the cartridge for one CPU cycle and forwards its interrupt output to the IRQ line
+pub fn run_mapper(mem: &mut CpuMemory) {
+    mem.mapper.borrow_mut().clock_cpu();
+    let mapper_irq = mem.mapper.borrow().is_irq_asserted();
+    set_irq(mem, IRQ_SOURCE_MAPPER, mapper_irq);
+}
+
+// APU and cartridge expansion audio mixed together
+pub fn audio_output(mem: &CpuMemory) -> f32 {
+    return apu::output(&mem.apu) + mem.mapper.borrow().audio_output();
+}
+
+// clocks the APU for one CPU cycle and forwards its interrupt outputs to the IRQ line
+pub fn run_apu(mem: &mut CpuMemory) {
+    apu::run(&mut mem.apu);
+    let frame_irq = apu::is_frame_irq(&mem.apu);
+    set_irq(mem, IRQ_SOURCE_FRAME_COUNTER, frame_irq);
+    let dmc_irq = apu::is_dmc_irq(&mem.apu);
+    set_irq(mem, IRQ_SOURCE_DMC, dmc_irq);
+}
@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+use serde_derive::Deserialize;
+
+use super::controller;
+
+// stick deflection needed before an axis counts as a press
+pub const AXIS_THRESHOLD: i16 = 16384;
+
+pub const TURBO_RATE_MIN: u32 = 1;
+pub const TURBO_RATE_MAX: u32 = 30;
+const DEFAULT_TURBO_RATE: u32 = 10;
+const FRAME_RATE: u32 = 60;
+
+// each input source keeps its own held buttons, so releasing a key doesn't
+// release the same button held on the pad; within a source the held buttons are
+// rebuilt from every input still down, so two inputs bound to one button don't clash
+const SOURCE_KEYBOARD: usize = 0;
+const SOURCE_BUTTONS: usize = 1;
+const SOURCE_AXES: usize = 2;
+const SOURCE_COUNT: usize = 3;
+
+// bindings map an input name to an NES button name
+// keyboard: SDL key names ("X", "Right Shift"), buttons: SDL controller
+// button names ("a", "dpup"), axes: "+"/"-" followed by the axis name ("-leftx")
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct PlayerConfig {
+    pub keyboard: HashMap<String, String>,
+    // slot of the game controller driving this player, in order of connection
+    pub controller: Option<usize>,
+    pub buttons: HashMap<String, String>,
+    pub axes: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    // turbo presses per second
+    pub turbo_rate: u32,
+    pub players: Vec<PlayerConfig>,
+}
+
+impl Default for PlayerConfig {
+    fn default() -> PlayerConfig {
+        return PlayerConfig {
+            keyboard: HashMap::new(),
+            controller: None,
+            buttons: HashMap::new(),
+            axes: HashMap::new(),
+        };
+    }
+}
+
+impl Default for InputConfig {
+    fn default() -> InputConfig {
+        return default_config();
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Target {
+    button: u8,
+    turbo: bool,
+}
+
+pub struct PlayerInput {
+    keyboard: HashMap<String, Target>,
+    controller: Option<usize>,
+    buttons: HashMap<String, Target>,
+    axes: HashMap<String, Target>,
+    // inputs currently down per source: key names, button names, axis bindings ("-leftx")
+    pressed: [HashSet<String>; SOURCE_COUNT],
+    // per source
+    held: [u8; SOURCE_COUNT],
+    turbo_held: [u8; SOURCE_COUNT],
+}
+
+pub struct Input {
+    players: Vec<PlayerInput>,
+    turbo_rate: u32,
+    frame: u32,
+}
+
+fn bindings(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    for (input, button) in pairs {
+        map.insert(input.to_string(), button.to_string());
+    }
+    return map;
+}
+
+fn default_pad_bindings() -> (HashMap<String, String>, HashMap<String, String>) {
+    let buttons = bindings(&[
+        ("a", "A"), ("b", "B"), ("x", "TurboB"), ("y", "TurboA"),
+        ("back", "Select"), ("start", "Start"),
+        ("dpup", "Up"), ("dpdown", "Down"), ("dpleft", "Left"), ("dpright", "Right"),
+    ]);
+    let axes = bindings(&[
+        ("-leftx", "Left"), ("+leftx", "Right"), ("-lefty", "Up"), ("+lefty", "Down"),
+    ]);
+    return (buttons, axes);
+}
+
+pub fn default_config() -> InputConfig {
+    let (buttons_1, axes_1) = default_pad_bindings();
+    let (buttons_2, axes_2) = default_pad_bindings();
+    return InputConfig {
+        turbo_rate: DEFAULT_TURBO_RATE,
+        players: vec![
+            PlayerConfig {
+                keyboard: bindings(&[
+                    ("X", "A"), ("Z", "B"), ("V", "TurboA"), ("C", "TurboB"),
+                    ("Right Shift", "Select"), ("Return", "Start"),
+                    ("Up", "Up"), ("Down", "Down"), ("Left", "Left"), ("Right", "Right"),
+                ]),
+                controller: Some(0),
+                buttons: buttons_1,
+                axes: axes_1,
+            },
+            PlayerConfig {
+                keyboard: bindings(&[
+                    ("G", "A"), ("F", "B"), ("T", "TurboA"), ("R", "TurboB"),
+                    ("Q", "Select"), ("E", "Start"),
+                    ("W", "Up"), ("S", "Down"), ("A", "Left"), ("D", "Right"),
+                ]),
+                controller: Some(1),
+                buttons: buttons_2,
+                axes: axes_2,
+            },
+        ],
+    };
+}
+
+// a missing file gives the default bindings
+pub fn load_config(filename: &str) -> InputConfig {
+    let path = Path::new(filename);
+    if !path.exists() {
+        return default_config();
+    }
+    let file = match File::open(&path) {
+        Err(why) => panic!("couldn't open {}: {}", path.display(), why),
+        Ok(file) => file,
+    };
+    match serde_json::from_reader(file) {
+        Err(why) => panic!("couldn't parse {}: {}", path.display(), why),
+        Ok(config) => return config,
+    }
+}
+
+fn parse_target(name: &str) -> Option<Target> {
+    let (button, turbo) = match name {
+        "A" => (controller::BUTTON_A, false),
+        "B" => (controller::BUTTON_B, false),
+        "Select" => (controller::BUTTON_SELECT, false),
+        "Start" => (controller::BUTTON_START, false),
+        "Up" => (controller::BUTTON_UP, false),
+        "Down" => (controller::BUTTON_DOWN, false),
+        "Left" => (controller::BUTTON_LEFT, false),
+        "Right" => (controller::BUTTON_RIGHT, false),
+        "TurboA" => (controller::BUTTON_A, true),
+        "TurboB" => (controller::BUTTON_B, true),
+        _ => return None,
+    };
+    return Some(Target { button: button, turbo: turbo });
+}
+
+fn parse_bindings(map: &HashMap<String, String>) -> HashMap<String, Target> {
+    let mut targets = HashMap::new();
+    for (input, name) in map {
+        match parse_target(name) {
+            Some(target) => { targets.insert(input.clone(), target); },
+            None => panic!("unknown NES button in input config: {}", name),
+        }
+    }
+    return targets;
+}
+
+pub fn new_input(config: &InputConfig) -> Input {
+    let mut players = Vec::new();
+    // the console has two controller ports
+    for player in config.players.iter().take(2) {
+        players.push(PlayerInput {
+            keyboard: parse_bindings(&player.keyboard),
+            controller: player.controller,
+            buttons: parse_bindings(&player.buttons),
+            axes: parse_bindings(&player.axes),
+            pressed: [HashSet::new(), HashSet::new(), HashSet::new()],
+            held: [0; SOURCE_COUNT],
+            turbo_held: [0; SOURCE_COUNT],
+        });
+    }
+    return Input {
+        players: players,
+        turbo_rate: clamp_turbo_rate(config.turbo_rate),
+        frame: 0,
+    };
+}
+
+fn clamp_turbo_rate(rate: u32) -> u32 {
+    return rate.max(TURBO_RATE_MIN).min(TURBO_RATE_MAX);
+}
+
+fn source_bindings(player: &PlayerInput, source: usize) -> &HashMap<String, Target> {
+    match source {
+        SOURCE_KEYBOARD => &player.keyboard,
+        SOURCE_BUTTONS => &player.buttons,
+        _ => &player.axes,
+    }
+}
+
+fn rebuild_held(player: &mut PlayerInput, source: usize) {
+    let mut held = 0;
+    let mut turbo_held = 0;
+    let bindings = source_bindings(player, source);
+    for input in player.pressed[source].iter() {
+        if let Some(target) = bindings.get(input) {
+            if target.turbo {
+                turbo_held = turbo_held | target.button;
+            } else {
+                held = held | target.button;
+            }
+        }
+    }
+    player.held[source] = held;
+    player.turbo_held[source] = turbo_held;
+}
+
+fn press(player: &mut PlayerInput, source: usize, input: &str, pressed: bool) {
+    if !source_bindings(player, source).contains_key(input) {
+        return;
+    }
+    if pressed {
+        player.pressed[source].insert(input.to_string());
+    } else {
+        player.pressed[source].remove(input);
+    }
+    rebuild_held(player, source);
+}
+
+pub fn key_event(input: &mut Input, key: &str, pressed: bool) {
+    for player in input.players.iter_mut() {
+        press(player, SOURCE_KEYBOARD, key, pressed);
+    }
+}
+
+pub fn button_event(input: &mut Input, slot: usize, button: &str, pressed: bool) {
+    for player in input.players.iter_mut() {
+        if player.controller != Some(slot) {
+            continue;
+        }
+        press(player, SOURCE_BUTTONS, button, pressed);
+    }
+}
+
+// only crossing the threshold presses or releases, so jitter around the centre is ignored
+fn axis_direction(player: &mut PlayerInput, binding: &str, pressed: bool) {
+    let was_pressed = player.pressed[SOURCE_AXES].contains(binding);
+    if pressed != was_pressed {
+        press(player, SOURCE_AXES, binding, pressed);
+    }
+}
+
+pub fn axis_event(input: &mut Input, slot: usize, axis: &str, value: i16) {
+    let negative = format!("-{}", axis);
+    let positive = format!("+{}", axis);
+    for player in input.players.iter_mut() {
+        if player.controller != Some(slot) {
+            continue;
+        }
+        axis_direction(player, &negative, value <= -AXIS_THRESHOLD);
+        axis_direction(player, &positive, value >= AXIS_THRESHOLD);
+    }
+}
+
+// an unplugged controller must not leave its buttons held
+pub fn release_device(input: &mut Input, slot: usize) {
+    for player in input.players.iter_mut() {
+        if player.controller == Some(slot) {
+            for source in [SOURCE_BUTTONS, SOURCE_AXES].iter() {
+                player.pressed[*source].clear();
+                rebuild_held(player, *source);
+            }
+        }
+    }
+}
+
+pub fn turbo_rate(input: &Input) -> u32 {
+    return input.turbo_rate;
+}
+
+pub fn set_turbo_rate(input: &mut Input, rate: u32) {
+    input.turbo_rate = clamp_turbo_rate(rate);
+}
+
+// called once per frame to latch the held buttons into the controllers
+pub fn update(input: &mut Input, controllers: &mut [controller::Controller]) {
+    // turbo buttons alternate pressed/released turbo_rate times per second
+    let turbo_on = (input.frame * input.turbo_rate * 2 / FRAME_RATE) % 2 == 0;
+    input.frame = (input.frame + 1) % FRAME_RATE;
+
+    for (player, controller) in input.players.iter().zip(controllers.iter_mut()) {
+        let mut buttons = 0;
+        for source in 0..SOURCE_COUNT {
+            buttons = buttons | player.held[source];
+            if turbo_on {
+                buttons = buttons | player.turbo_held[source];
+            }
+        }
+        controller::set_buttons(controller, buttons);
+    }
+}
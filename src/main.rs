@@ -5,14 +5,123 @@ use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
 use sdl2::event::Event;
 use sdl2::keyboard::Keycode;
+use sdl2::audio::AudioSpecDesired;
+use sdl2::controller::GameController;
 use std::env;
+use log::warn;
 
 mod rom;
 mod cpu;
 mod cpu_memory;
 mod ppu;
+mod apu;
+mod console;
+mod audio;
+mod vgm;
+mod controller;
+mod input;
+mod mapper;
+
+const AUDIO_SAMPLE_RATE: i32 = 48000;
+// audio kept queued ahead of playback, in frames
+const AUDIO_LATENCY_FRAMES: f64 = 3.0;
+
+// frames emulated by a headless capture unless --frames is given (one minute)
+const DEFAULT_CAPTURE_FRAMES: u32 = 3600;
+
+const DEFAULT_INPUT_CONFIG: &str = "input.json";
+
+// places a newly attached game controller in the first free slot
+fn attach_controller(slots: &mut Vec<Option<GameController>>, controller: GameController) {
+    match slots.iter().position(|slot| slot.is_none()) {
+        Some(index) => slots[index] = Some(controller),
+        None => slots.push(Some(controller)),
+    }
+}
+
+fn controller_slot(slots: &Vec<Option<GameController>>, instance_id: i32) -> Option<usize> {
+    return slots.iter().position(|slot| match slot {
+        Some(controller) => controller.instance_id() == instance_id,
+        None => false,
+    });
+}
+
+// one CPU cycle of the whole console
+fn step(cpu: &mut cpu::Cpu, mem: &mut cpu_memory::CpuMemory) {
+    cpu::run(cpu, mem);
+    cpu_memory::run_apu(mem);
+    cpu_memory::run_mapper(mem);
+
+    // the PPU runs 3 dots per CPU cycle
+    for _ in 0..3 {
+        ppu::run(&mut mem.ppu);
+    }
+}
+
+// runs without a window, logging APU register writes to a VGM file
+fn run_headless(cpu: &mut cpu::Cpu, mem: &mut cpu_memory::CpuMemory, frames: u32, vgm_path: &str) {
+    mem.vgm_log = Some(vgm::new_vgm_log());
+
+    let mut v_canvas = vec![0; 256*256*3];
+    let mut frame = 0;
+    while frame < frames {
+        step(cpu, mem);
+        if ppu::is_draw_timing(mem.ppu) {
+            ppu::draw_to_canvas(&mut v_canvas, &mut mem.ppu);
+            frame += 1;
+        }
+    }
+
+    if let Some(log) = mem.vgm_log.take() {
+        match vgm::save_vgm(&log, mem.cycles, audio::CPU_CLOCK_RATE, vgm_path) {
+            Err(why) => panic!("couldn't write {}: {}", vgm_path, why),
+            Ok(_) => {}
+        }
+    }
+}
 
 fn main() {
+    let mut rom_path = String::new();
+    let mut ram_init = cpu_memory::RamInit::Zero;
+    let mut sprite_limit = true;
+    let mut vgm_path = None;
+    let mut capture_frames = DEFAULT_CAPTURE_FRAMES;
+    let mut input_config_path = DEFAULT_INPUT_CONFIG.to_string();
+    for arg in env::args().skip(1) {
+        if arg.starts_with("--ram-init=") {
+            ram_init = match cpu_memory::parse_ram_init(&arg["--ram-init=".len()..]) {
+                Some(init) => init,
+                None => panic!("unknown ram init policy: {} (zero, ff, random)", arg),
+            };
+        } else if arg == "--no-sprite-limit" {
+            sprite_limit = false;
+        } else if arg.starts_with("--vgm=") {
+            vgm_path = Some(arg["--vgm=".len()..].to_string());
+        } else if arg.starts_with("--frames=") {
+            capture_frames = match arg["--frames=".len()..].parse() {
+                Ok(frames) => frames,
+                Err(_) => panic!("invalid frame count: {}", arg),
+            };
+        } else if arg.starts_with("--input-config=") {
+            input_config_path = arg["--input-config=".len()..].to_string();
+        } else {
+            rom_path = arg;
+        }
+    }
+
+    let nes_rom = rom::load_nes(&rom_path);
+    let mut cpu = cpu::new_cpu();
+    let cartridge = mapper::new_mapper(&nes_rom);
+    let mut ppu = ppu::new_ppu(cartridge.clone());
+    ppu::set_sprite_limit(&mut ppu, sprite_limit);
+    let mut mem = cpu_memory::new_memory(cartridge, &mut ppu);
+    console::power_on(&mut cpu, &mut mem, ram_init);
+
+    if let Some(path) = vgm_path {
+        run_headless(&mut cpu, &mut mem, capture_frames, &path);
+        return;
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem.window("nes", 800, 600)
@@ -21,10 +130,17 @@ fn main() {
         .build()
         .unwrap();
 
-    let nes_rom = rom::load_nes(&env::args().nth(1).unwrap());
-    let mut cpu = cpu::new_cpu();
-    let mut ppu = ppu::new_ppu(&nes_rom.character_rom.data);
-    let mut mem = cpu_memory::new_memory(&nes_rom.program_rom.data, &mut ppu);
+    let audio_subsystem = sdl_context.audio().unwrap();
+    let desired_spec = AudioSpecDesired {
+        freq: Some(AUDIO_SAMPLE_RATE),
+        channels: Some(1),
+        samples: Some(1024),
+    };
+    let audio_queue = audio_subsystem.open_queue::<f32, _>(None, &desired_spec).unwrap();
+    let sample_rate = audio_queue.spec().freq as f64;
+    let mut resampler = audio::new_resampler(audio::CPU_CLOCK_RATE, sample_rate);
+    let target_queue_bytes = ((sample_rate / 60.0 * AUDIO_LATENCY_FRAMES) as u32) * 4;
+    audio_queue.resume();
 
     let mut canvas = window.into_canvas().build().unwrap();
     let texture_creator = canvas.texture_creator();
@@ -32,31 +148,19 @@ fn main() {
     let mut texture = texture_creator.create_texture_streaming(
         PixelFormatEnum::RGB24, 256, 256).unwrap();
     let mut event_pump = sdl_context.event_pump().unwrap();
-    let mut v_canvas = vec![0; 256*256*3];
 
-    let mut ppu_cycle = 2;
+    let mut input = input::new_input(&input::load_config(&input_config_path));
+    // controllers already plugged in are reported through ControllerDeviceAdded too
+    let game_controller_subsystem = sdl_context.game_controller().unwrap();
+    let mut controller_slots: Vec<Option<GameController>> = Vec::new();
+    let mut v_canvas = vec![0; 256*256*3];
 
     'main: loop {
-        for event in event_pump.poll_iter() {
-            match event {
-                Event::Quit {..} 
-                | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-                    break 'main
-                },
-                _ => {}
-            }
-        }
-
         // println!("0x6000 = {:04X}", cpu_memory::read_mem_word(&mut mem, 0x6000));
 
         // println!("---");
-        cpu::run(&mut cpu, &mut mem);
-
-        ppu_cycle = ppu_cycle - 1;
-        if ppu_cycle <= 0 {
-            ppu::run(&mut mem.ppu);
-            ppu_cycle = 2;
-        }
+        step(&mut cpu, &mut mem);
+        audio::push_sample(&mut resampler, cpu_memory::audio_output(&mem));
 
         if ppu::is_draw_timing(mem.ppu) {
             ppu::draw_to_canvas(&mut v_canvas, &mut mem.ppu);
@@ -76,7 +180,72 @@ fn main() {
             canvas.copy(&texture, Some(Rect::new(0, 0, 256, 256)), Some(Rect::new(0, 0, 512, 512))).unwrap();
             canvas.present();
 
-            ::std::thread::sleep(Duration::new(0, 1_000_000_000u32 / 60));
+            audio_queue.queue(&audio::take_samples(&mut resampler));
+            // rate control sees how far the queue is from the target before pacing pulls it back
+            audio::set_fill_level(&mut resampler, audio_queue.size() as f64 / target_queue_bytes as f64);
+            // pace emulation on the audio queue: wait while more than the target is buffered
+            while audio_queue.size() > target_queue_bytes {
+                ::std::thread::sleep(Duration::from_millis(1));
+            }
+
+            // input is polled once per frame
+            for event in event_pump.poll_iter() {
+                match event {
+                    Event::Quit {..}
+                    | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                        break 'main
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                        console::reset(&mut cpu, &mut mem);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                        console::power_on(&mut cpu, &mut mem, ram_init);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                        let rate = input::turbo_rate(&input);
+                        input::set_turbo_rate(&mut input, rate - 1);
+                    },
+                    Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                        let rate = input::turbo_rate(&input);
+                        input::set_turbo_rate(&mut input, rate + 1);
+                    },
+                    Event::KeyDown { keycode: Some(keycode), repeat: false, .. } => {
+                        input::key_event(&mut input, &keycode.name(), true);
+                    },
+                    Event::KeyUp { keycode: Some(keycode), .. } => {
+                        input::key_event(&mut input, &keycode.name(), false);
+                    },
+                    Event::ControllerDeviceAdded { which, .. } => {
+                        match game_controller_subsystem.open(which) {
+                            Ok(controller) => attach_controller(&mut controller_slots, controller),
+                            Err(why) => warn!("couldn't open game controller {}: {}", which, why),
+                        }
+                    },
+                    Event::ControllerDeviceRemoved { which, .. } => {
+                        if let Some(slot) = controller_slot(&controller_slots, which) {
+                            controller_slots[slot] = None;
+                            input::release_device(&mut input, slot);
+                        }
+                    },
+                    Event::ControllerButtonDown { which, button, .. } => {
+                        if let Some(slot) = controller_slot(&controller_slots, which) {
+                            input::button_event(&mut input, slot, &button.string(), true);
+                        }
+                    },
+                    Event::ControllerButtonUp { which, button, .. } => {
+                        if let Some(slot) = controller_slot(&controller_slots, which) {
+                            input::button_event(&mut input, slot, &button.string(), false);
+                        }
+                    },
+                    Event::ControllerAxisMotion { which, axis, value, .. } => {
+                        if let Some(slot) = controller_slot(&controller_slots, which) {
+                            input::axis_event(&mut input, slot, &axis.string(), value);
+                        }
+                    },
+                    _ => {}
+                }
+            }
+            input::update(&mut input, &mut mem.controllers);
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,156 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use super::ppu;
+use super::rom;
+
+mod nrom;
+mod mmc1;
+mod uxrom;
+mod cnrom;
+mod axrom;
+mod gxrom;
+mod mmc3;
+mod mmc2;
+mod mmc5;
+mod vrc_irq;
+mod vrc4;
+mod vrc6;
+mod opll;
+mod vrc7;
+
+// boards without CHR ROM carry 8KB of CHR RAM
+const CHR_RAM_SIZE: usize = 0x2000;
+// boards get 8KB of PRG RAM unless the header asks for more
+const PRG_RAM_SIZE: usize = 0x2000;
+
+// everything on the cartridge side of the CPU ($4020-$FFFF) and PPU ($0000-$3EFF) buses
+pub trait Mapper {
+    // None leaves the CPU data bus floating (open bus)
+    fn read_prg(&mut self, addr: u16) -> Option<u8>;
+    fn write_prg(&mut self, addr: u16, value: u8);
+    fn read_chr(&mut self, addr: u16) -> u8;
+    fn write_chr(&mut self, addr: u16, value: u8);
+    fn mirroring(&self) -> ppu::Mirroring;
+
+    // $2000-$2FFF; boards that don't map their own memory there use the console VRAM
+    fn read_nametable(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        return vram[ppu::nametable_index(self.mirroring(), addr)];
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, vram: &mut [u8]) {
+        vram[ppu::nametable_index(self.mirroring(), addr)] = value;
+    }
+
+    // every address the PPU drives, including pattern fetches and $2006/$2007 accesses
+    fn ppu_bus_address(&mut self, _addr: u16) {
+    }
+
+    // the PPU starts fetching sprites or background for a line, or enters vblank
+    fn ppu_fetch_phase(&mut self, _phase: u8, _line: u16, _tall_sprites: bool) {
+    }
+
+    // called once per CPU cycle
+    fn clock_cpu(&mut self) {
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        return false;
+    }
+
+    // expansion audio, on the same scale as apu::output
+    fn audio_output(&self) -> f32 {
+        return 0.0;
+    }
+}
+
+// expansion audio mixes linearly; one volume step of a square channel, scaled to match the APU pulses
+pub const AUDIO_PULSE_SCALE: f32 = 0.00752;
+
+// parts of the PPU fetch schedule, for boards that substitute fetched data (MMC5)
+pub const PPU_FETCH_IDLE: u8 = 0;
+// dots 257-320
+pub const PPU_FETCH_SPRITES: u8 = 1;
+// dots 321-336 fetch the first two tiles of the next line, dots 1-256 the rest
+pub const PPU_FETCH_BACKGROUND: u8 = 2;
+
+pub type SharedMapper = Rc<RefCell<Box<dyn Mapper>>>;
+
+// memory found on every board
+pub struct Cartridge {
+    pub prg_rom: Vec<u8>,
+    pub chr: Vec<u8>,
+    pub chr_ram: bool,
+    pub prg_ram: Vec<u8>,
+    pub mirroring: ppu::Mirroring,
+}
+
+pub fn new_cartridge(nes_rom: &rom::NesRom) -> Cartridge {
+    let chr_ram = nes_rom.character_rom.data.len() == 0;
+    let chr = if chr_ram { vec![0; CHR_RAM_SIZE] } else { nes_rom.character_rom.data.clone() };
+    return Cartridge {
+        prg_rom: nes_rom.program_rom.data.clone(),
+        chr: chr,
+        chr_ram: chr_ram,
+        prg_ram: vec![0; rom::prg_ram_size(&nes_rom.header).max(PRG_RAM_SIZE)],
+        mirroring: rom::mirroring(&nes_rom.header),
+    };
+}
+
+// bank numbers wrap around the ROM size, like unconnected upper bank lines
+pub fn read_prg_bank(cartridge: &Cartridge, bank_size: usize, bank: usize, offset: u16) -> u8 {
+    let banks = (cartridge.prg_rom.len() / bank_size).max(1);
+    let index = (bank % banks) * bank_size + (offset as usize) % bank_size;
+    return cartridge.prg_rom[index % cartridge.prg_rom.len()];
+}
+
+pub fn read_chr_bank(cartridge: &Cartridge, bank_size: usize, bank: usize, offset: u16) -> u8 {
+    let banks = (cartridge.chr.len() / bank_size).max(1);
+    let index = (bank % banks) * bank_size + (offset as usize) % bank_size;
+    return cartridge.chr[index % cartridge.chr.len()];
+}
+
+// CHR ROM ignores writes
+pub fn write_chr_bank(cartridge: &mut Cartridge, bank_size: usize, bank: usize, offset: u16, value: u8) {
+    if !cartridge.chr_ram {
+        return;
+    }
+    let banks = (cartridge.chr.len() / bank_size).max(1);
+    let index = (bank % banks) * bank_size + (offset as usize) % bank_size;
+    let len = cartridge.chr.len();
+    cartridge.chr[index % len] = value;
+}
+
+// NES 2.0 submappers 1 and 2 of the discrete boards (2, 3, 7) say whether the board has
+// bus conflicts; submapper 0 leaves it to the usual board for the mapper
+fn has_bus_conflicts(submapper: u8, default: bool) -> bool {
+    match submapper {
+        1 => false,
+        2 => true,
+        _ => default,
+    }
+}
+
+pub fn new_mapper(nes_rom: &rom::NesRom) -> SharedMapper {
+    let cartridge = new_cartridge(nes_rom);
+    let number = rom::mapper_number(&nes_rom.header);
+    let submapper = rom::submapper(&nes_rom.header);
+    let mapper: Box<dyn Mapper> = match number {
+        0 => Box::new(nrom::new_nrom(cartridge)),
+        1 => Box::new(mmc1::new_mmc1(cartridge)),
+        2 => Box::new(uxrom::new_uxrom(cartridge, has_bus_conflicts(submapper, true))),
+        3 => Box::new(cnrom::new_cnrom(cartridge, has_bus_conflicts(submapper, true))),
+        4 => Box::new(mmc3::new_mmc3(cartridge)),
+        5 => Box::new(mmc5::new_mmc5(cartridge)),
+        // AOROM, the common AxROM board, has no bus conflicts
+        7 => Box::new(axrom::new_axrom(cartridge, has_bus_conflicts(submapper, false))),
+        9 => Box::new(mmc2::new_mmc2(cartridge)),
+        10 => Box::new(mmc2::new_mmc4(cartridge)),
+        21 | 22 | 23 | 25 => Box::new(vrc4::new_vrc4(cartridge, number, submapper)),
+        24 => Box::new(vrc6::new_vrc6a(cartridge)),
+        26 => Box::new(vrc6::new_vrc6b(cartridge)),
+        66 => Box::new(gxrom::new_gxrom(cartridge, true)),
+        85 => Box::new(vrc7::new_vrc7(cartridge)),
+        _ => panic!("unsupported mapper: {}", number),
+    };
+    return Rc::new(RefCell::new(mapper));
+}
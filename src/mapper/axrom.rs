@@ -0,0 +1,55 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// mapper 7: switchable 32KB PRG and a single-screen nametable select
+pub struct Axrom {
+    cartridge: Cartridge,
+    prg_bank: u8,
+    upper_nametable: bool,
+    // ANROM/AMROM have them, AOROM doesn't
+    bus_conflicts: bool,
+}
+
+pub fn new_axrom(cartridge: Cartridge, bus_conflicts: bool) -> Axrom {
+    return Axrom {
+        cartridge: cartridge,
+        prg_bank: 0,
+        upper_nametable: false,
+        bus_conflicts: bus_conflicts,
+    };
+}
+
+impl Mapper for Axrom {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            return Some(read_prg_bank(&self.cartridge, 0x8000, (self.prg_bank & 0x07) as usize, addr - 0x8000));
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            let mut value = value;
+            if self.bus_conflicts {
+                value = value & self.read_prg(addr).unwrap_or(0xFF);
+            }
+            self.prg_bank = value & 0x07;
+            self.upper_nametable = (value & 0x10) != 0;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        return read_chr_bank(&self.cartridge, 0x2000, 0, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        write_chr_bank(&mut self.cartridge, 0x2000, 0, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        if self.upper_nametable {
+            return ppu::Mirroring::SingleScreenUpper;
+        }
+        return ppu::Mirroring::SingleScreenLower;
+    }
+}
@@ -0,0 +1,48 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// mapper 3: NROM layout with a switchable 8KB CHR bank
+pub struct Cnrom {
+    cartridge: Cartridge,
+    chr_bank: u8,
+    bus_conflicts: bool,
+}
+
+pub fn new_cnrom(cartridge: Cartridge, bus_conflicts: bool) -> Cnrom {
+    return Cnrom {
+        cartridge: cartridge,
+        chr_bank: 0,
+        bus_conflicts: bus_conflicts,
+    };
+}
+
+impl Mapper for Cnrom {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            return Some(read_prg_bank(&self.cartridge, 0x8000, 0, addr - 0x8000));
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            let mut value = value;
+            if self.bus_conflicts {
+                value = value & self.read_prg(addr).unwrap_or(0xFF);
+            }
+            self.chr_bank = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        return read_chr_bank(&self.cartridge, 0x2000, self.chr_bank as usize, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        write_chr_bank(&mut self.cartridge, 0x2000, self.chr_bank as usize, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        return self.cartridge.mirroring;
+    }
+}
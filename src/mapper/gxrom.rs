@@ -0,0 +1,51 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// mapper 66: switchable 32KB PRG (bits 4-5) and 8KB CHR (bits 0-1)
+pub struct Gxrom {
+    cartridge: Cartridge,
+    prg_bank: u8,
+    chr_bank: u8,
+    bus_conflicts: bool,
+}
+
+pub fn new_gxrom(cartridge: Cartridge, bus_conflicts: bool) -> Gxrom {
+    return Gxrom {
+        cartridge: cartridge,
+        prg_bank: 0,
+        chr_bank: 0,
+        bus_conflicts: bus_conflicts,
+    };
+}
+
+impl Mapper for Gxrom {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            return Some(read_prg_bank(&self.cartridge, 0x8000, self.prg_bank as usize, addr - 0x8000));
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            let mut value = value;
+            if self.bus_conflicts {
+                value = value & self.read_prg(addr).unwrap_or(0xFF);
+            }
+            self.prg_bank = (value >> 4) & 0x03;
+            self.chr_bank = value & 0x03;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        return read_chr_bank(&self.cartridge, 0x2000, self.chr_bank as usize, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        write_chr_bank(&mut self.cartridge, 0x2000, self.chr_bank as usize, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        return self.cartridge.mirroring;
+    }
+}
@@ -0,0 +1,193 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// control register ($8000-$9FFF)
+const CONTROL_MIRRORING: u8 = 0x03;
+const CONTROL_PRG_MODE: u8 = 0x0C;
+const CONTROL_CHR_4K: u8 = 0x10;
+
+// PRG register bit 4 disables PRG RAM (MMC1B and later)
+const PRG_RAM_DISABLE: u8 = 0x10;
+
+// boards with 512KB of PRG ROM (SUROM/SXROM) use CHR bit 4 as the 256KB outer bank
+const PRG_OUTER_BANK_SIZE: usize = 0x40000;
+
+// mapper 1: a 5-bit serial port loading four internal registers
+pub struct Mmc1 {
+    cartridge: Cartridge,
+    shift: u8,
+    shift_count: u8,
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+    // in 4KB CHR mode the outer bank bits come from the register PPU A12 last selected
+    chr_a12: bool,
+    // writes on consecutive CPU cycles (read-modify-write instructions) only take the first
+    cycle: u64,
+    last_write_cycle: u64,
+}
+
+pub fn new_mmc1(cartridge: Cartridge) -> Mmc1 {
+    return Mmc1 {
+        cartridge: cartridge,
+        shift: 0,
+        shift_count: 0,
+        // power on in fix-last PRG mode so the reset vector is reachable
+        control: CONTROL_PRG_MODE,
+        chr_bank_0: 0,
+        chr_bank_1: 0,
+        prg_bank: 0,
+        chr_a12: false,
+        cycle: 0,
+        last_write_cycle: 0,
+    };
+}
+
+// CHR register driving the board specific lines (PRG outer bank, PRG RAM bank)
+fn outer_register(mmc1: &Mmc1) -> u8 {
+    if (mmc1.control & CONTROL_CHR_4K) != 0 && mmc1.chr_a12 {
+        return mmc1.chr_bank_1;
+    }
+    return mmc1.chr_bank_0;
+}
+
+fn prg_outer_bank(mmc1: &Mmc1) -> usize {
+    if mmc1.cartridge.prg_rom.len() <= PRG_OUTER_BANK_SIZE {
+        return 0;
+    }
+    return ((outer_register(mmc1) >> 4) & 1) as usize;
+}
+
+fn prg_ram_bank(mmc1: &Mmc1) -> usize {
+    // only CHR RAM boards have spare CHR lines to bank PRG RAM with
+    if !mmc1.cartridge.chr_ram {
+        return 0;
+    }
+    let register = outer_register(mmc1);
+    match mmc1.cartridge.prg_ram.len() {
+        // SOROM: 16KB, bit 3
+        0x4000 => ((register >> 3) & 1) as usize,
+        // SXROM: 32KB, bits 2-3
+        0x8000 => ((register >> 2) & 3) as usize,
+        _ => 0,
+    }
+}
+
+fn prg_ram_index(mmc1: &Mmc1, addr: u16) -> usize {
+    let index = prg_ram_bank(mmc1) * 0x2000 + (addr - 0x6000) as usize;
+    return index % mmc1.cartridge.prg_ram.len();
+}
+
+fn prg_bank_16k(mmc1: &Mmc1, addr: u16) -> usize {
+    let bank = (mmc1.prg_bank & 0x0F) as usize;
+    let upper = addr >= 0xC000;
+    let bank = match (mmc1.control & CONTROL_PRG_MODE) >> 2 {
+        // 32KB mode ignores the low bit
+        0 | 1 => (bank & !1) | (if upper { 1 } else { 0 }),
+        // first bank fixed at $8000
+        2 => if upper { bank } else { 0 },
+        // last bank fixed at $C000
+        _ => if upper { 0x0F } else { bank },
+    };
+    return prg_outer_bank(mmc1) * 16 + bank;
+}
+
+fn chr_bank_4k(mmc1: &Mmc1, addr: u16) -> usize {
+    if (mmc1.control & CONTROL_CHR_4K) == 0 {
+        // 8KB mode ignores the low bit
+        return ((mmc1.chr_bank_0 & 0x1E) | ((addr >> 12) & 1) as u8) as usize;
+    }
+    if addr < 0x1000 {
+        return mmc1.chr_bank_0 as usize;
+    }
+    return mmc1.chr_bank_1 as usize;
+}
+
+fn write_register(mmc1: &mut Mmc1, addr: u16, value: u8) {
+    match (addr >> 13) & 0x03 {
+        0 => mmc1.control = value,
+        1 => mmc1.chr_bank_0 = value,
+        2 => mmc1.chr_bank_1 = value,
+        _ => mmc1.prg_bank = value,
+    }
+}
+
+fn write_serial(mmc1: &mut Mmc1, addr: u16, value: u8) {
+    let consecutive = mmc1.cycle <= mmc1.last_write_cycle + 1;
+    mmc1.last_write_cycle = mmc1.cycle;
+    if consecutive {
+        return;
+    }
+
+    if (value & 0x80) != 0 {
+        // reset also returns PRG to fix-last mode
+        mmc1.shift = 0;
+        mmc1.shift_count = 0;
+        mmc1.control = mmc1.control | CONTROL_PRG_MODE;
+        return;
+    }
+
+    // bits arrive LSB first
+    mmc1.shift = (mmc1.shift >> 1) | ((value & 1) << 4);
+    mmc1.shift_count += 1;
+    if mmc1.shift_count == 5 {
+        // the address of the fifth write selects the register
+        let shift = mmc1.shift;
+        write_register(mmc1, addr, shift);
+        mmc1.shift = 0;
+        mmc1.shift_count = 0;
+    }
+}
+
+impl Mapper for Mmc1 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            let bank = prg_bank_16k(self, addr);
+            return Some(read_prg_bank(&self.cartridge, 0x4000, bank, addr));
+        } else if addr >= 0x6000 {
+            if (self.prg_bank & PRG_RAM_DISABLE) != 0 {
+                return None;
+            }
+            return Some(self.cartridge.prg_ram[prg_ram_index(self, addr)]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            write_serial(self, addr, value);
+        } else if addr >= 0x6000 {
+            if (self.prg_bank & PRG_RAM_DISABLE) != 0 {
+                return;
+            }
+            let index = prg_ram_index(self, addr);
+            self.cartridge.prg_ram[index] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        self.chr_a12 = (addr & 0x1000) != 0;
+        let bank = chr_bank_4k(self, addr);
+        return read_chr_bank(&self.cartridge, 0x1000, bank, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr_a12 = (addr & 0x1000) != 0;
+        let bank = chr_bank_4k(self, addr);
+        write_chr_bank(&mut self.cartridge, 0x1000, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        match self.control & CONTROL_MIRRORING {
+            0 => ppu::Mirroring::SingleScreenLower,
+            1 => ppu::Mirroring::SingleScreenUpper,
+            2 => ppu::Mirroring::Vertical,
+            _ => ppu::Mirroring::Horizontal,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        self.cycle += 1;
+    }
+}
@@ -0,0 +1,112 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+const LATCH_FD: usize = 0;
+const LATCH_FE: usize = 1;
+
+// mappers 9 (MMC2) and 10 (MMC4): each 4KB pattern table has two banks, picked by a latch
+// the PPU flips when it fetches the high plane of tile $FD or $FE
+pub struct Mmc2 {
+    cartridge: Cartridge,
+    // MMC4 switches 16KB at $8000 and has PRG RAM; MMC2 switches 8KB
+    mmc4: bool,
+    prg_bank: u8,
+    // [pattern table][latch]
+    chr_banks: [[u8; 2]; 2],
+    latches: [usize; 2],
+    mirroring: ppu::Mirroring,
+}
+
+fn new_board(cartridge: Cartridge, mmc4: bool) -> Mmc2 {
+    let mirroring = cartridge.mirroring;
+    return Mmc2 {
+        cartridge: cartridge,
+        mmc4: mmc4,
+        prg_bank: 0,
+        chr_banks: [[0; 2]; 2],
+        latches: [LATCH_FE, LATCH_FE],
+        mirroring: mirroring,
+    };
+}
+
+pub fn new_mmc2(cartridge: Cartridge) -> Mmc2 {
+    return new_board(cartridge, false);
+}
+
+pub fn new_mmc4(cartridge: Cartridge) -> Mmc2 {
+    return new_board(cartridge, true);
+}
+
+fn chr_bank_4k(mmc2: &Mmc2, addr: u16) -> usize {
+    let table = ((addr >> 12) & 1) as usize;
+    return mmc2.chr_banks[table][mmc2.latches[table]] as usize;
+}
+
+// the new bank applies from the next fetch on
+fn update_latch(mmc2: &mut Mmc2, addr: u16) {
+    // MMC2 only reacts to $0FD8/$0FE8 in the first table, everything else to the 8 byte ranges
+    if !mmc2.mmc4 && addr < 0x1000 && (addr & 0x07) != 0 {
+        return;
+    }
+    let table = ((addr >> 12) & 1) as usize;
+    match addr & 0x0FF8 {
+        0x0FD8 => mmc2.latches[table] = LATCH_FD,
+        0x0FE8 => mmc2.latches[table] = LATCH_FE,
+        _ => {}
+    }
+}
+
+impl Mapper for Mmc2 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            if self.mmc4 {
+                let last = (self.cartridge.prg_rom.len() / 0x4000).max(1) - 1;
+                let bank = if addr < 0xC000 { (self.prg_bank & 0x0F) as usize } else { last };
+                return Some(read_prg_bank(&self.cartridge, 0x4000, bank, addr));
+            }
+            // the last three 8KB banks are fixed; a ROM under 32KB wraps through read_prg_bank
+            let banks = (self.cartridge.prg_rom.len() / 0x2000).max(4);
+            let bank = if addr < 0xA000 { (self.prg_bank & 0x0F) as usize } else { banks - 4 + ((addr - 0x8000) >> 13) as usize };
+            return Some(read_prg_bank(&self.cartridge, 0x2000, bank, addr));
+        } else if addr >= 0x6000 && self.mmc4 {
+            return Some(self.cartridge.prg_ram[(addr - 0x6000) as usize % self.cartridge.prg_ram.len()]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        match addr & 0xF000 {
+            0xA000 => self.prg_bank = value,
+            0xB000 => self.chr_banks[0][LATCH_FD] = value & 0x1F,
+            0xC000 => self.chr_banks[0][LATCH_FE] = value & 0x1F,
+            0xD000 => self.chr_banks[1][LATCH_FD] = value & 0x1F,
+            0xE000 => self.chr_banks[1][LATCH_FE] = value & 0x1F,
+            0xF000 => {
+                self.mirroring = if (value & 1) != 0 { ppu::Mirroring::Horizontal } else { ppu::Mirroring::Vertical };
+            }
+            0x6000 | 0x7000 => {
+                if self.mmc4 {
+                    let len = self.cartridge.prg_ram.len();
+                    self.cartridge.prg_ram[(addr - 0x6000) as usize % len] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = chr_bank_4k(self, addr);
+        let value = read_chr_bank(&self.cartridge, 0x1000, bank, addr);
+        update_latch(self, addr);
+        return value;
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = chr_bank_4k(self, addr);
+        write_chr_bank(&mut self.cartridge, 0x1000, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        return self.mirroring;
+    }
+}
@@ -0,0 +1,174 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// bank select ($8000)
+const SELECT_REGISTER: u8 = 0x07;
+const SELECT_PRG_MODE: u8 = 0x40;
+const SELECT_CHR_INVERSION: u8 = 0x80;
+
+// PRG RAM protect ($A001)
+const PRG_RAM_ENABLE: u8 = 0x80;
+const PRG_RAM_WRITE_PROTECT: u8 = 0x40;
+
+// A12 has to stay low this many CPU cycles before a rise clocks the counter; enough to
+// ignore the 9 dot gap before the first background fetch when backgrounds use $1000
+const A12_FILTER_CYCLES: u64 = 4;
+
+// mapper 4: eight bank registers and a scanline counter clocked by PPU A12
+pub struct Mmc3 {
+    cartridge: Cartridge,
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: ppu::Mirroring,
+    prg_ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq: bool,
+    a12_high: bool,
+    a12_low_cycle: u64,
+    cycle: u64,
+}
+
+pub fn new_mmc3(cartridge: Cartridge) -> Mmc3 {
+    let mirroring = cartridge.mirroring;
+    return Mmc3 {
+        cartridge: cartridge,
+        bank_select: 0,
+        banks: [0, 2, 4, 5, 6, 7, 0, 1],
+        mirroring: mirroring,
+        prg_ram_protect: PRG_RAM_ENABLE,
+        irq_latch: 0,
+        irq_counter: 0,
+        irq_reload: false,
+        irq_enabled: false,
+        irq: false,
+        a12_high: false,
+        a12_low_cycle: 0,
+        cycle: 0,
+    };
+}
+
+fn prg_bank_8k(mmc3: &Mmc3, addr: u16) -> usize {
+    // clamped so a ROM under 16KB wraps through read_prg_bank instead of underflowing
+    let second_last = (mmc3.cartridge.prg_rom.len() / 0x2000).max(2) - 2;
+    let swapped = (mmc3.bank_select & SELECT_PRG_MODE) != 0;
+    match (addr >> 13) & 0x03 {
+        0 => if swapped { second_last } else { (mmc3.banks[6] & 0x3F) as usize },
+        1 => (mmc3.banks[7] & 0x3F) as usize,
+        2 => if swapped { (mmc3.banks[6] & 0x3F) as usize } else { second_last },
+        _ => second_last + 1,
+    }
+}
+
+fn chr_bank_1k(mmc3: &Mmc3, addr: u16) -> usize {
+    // inversion swaps the 2KB and 1KB halves of the pattern tables
+    let addr = if (mmc3.bank_select & SELECT_CHR_INVERSION) != 0 { addr ^ 0x1000 } else { addr };
+    let slot = (addr >> 10) as usize;
+    match slot {
+        0 | 1 => ((mmc3.banks[0] & 0xFE) as usize) + slot,
+        2 | 3 => ((mmc3.banks[1] & 0xFE) as usize) + slot - 2,
+        _ => mmc3.banks[slot - 2] as usize,
+    }
+}
+
+fn clock_irq_counter(mmc3: &mut Mmc3) {
+    if mmc3.irq_counter == 0 || mmc3.irq_reload {
+        mmc3.irq_counter = mmc3.irq_latch;
+        mmc3.irq_reload = false;
+    } else {
+        mmc3.irq_counter -= 1;
+    }
+    if mmc3.irq_counter == 0 && mmc3.irq_enabled {
+        mmc3.irq = true;
+    }
+}
+
+fn write_register(mmc3: &mut Mmc3, addr: u16, value: u8) {
+    // registers are selected by A13-A14 and A0
+    let even = (addr & 1) == 0;
+    match ((addr >> 13) & 0x03, even) {
+        (0, true) => mmc3.bank_select = value,
+        (0, false) => mmc3.banks[(mmc3.bank_select & SELECT_REGISTER) as usize] = value,
+        (1, true) => {
+            if mmc3.cartridge.mirroring != ppu::Mirroring::FourScreen {
+                mmc3.mirroring = if (value & 1) != 0 { ppu::Mirroring::Horizontal } else { ppu::Mirroring::Vertical };
+            }
+        }
+        (1, false) => mmc3.prg_ram_protect = value,
+        (2, true) => mmc3.irq_latch = value,
+        (2, false) => {
+            mmc3.irq_counter = 0;
+            mmc3.irq_reload = true;
+        }
+        (_, true) => {
+            mmc3.irq_enabled = false;
+            mmc3.irq = false;
+        }
+        (_, false) => mmc3.irq_enabled = true,
+    }
+}
+
+impl Mapper for Mmc3 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            let bank = prg_bank_8k(self, addr);
+            return Some(read_prg_bank(&self.cartridge, 0x2000, bank, addr));
+        } else if addr >= 0x6000 {
+            if (self.prg_ram_protect & PRG_RAM_ENABLE) == 0 {
+                return None;
+            }
+            let len = self.cartridge.prg_ram.len();
+            return Some(self.cartridge.prg_ram[(addr - 0x6000) as usize % len]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            write_register(self, addr, value);
+        } else if addr >= 0x6000 {
+            if (self.prg_ram_protect & PRG_RAM_ENABLE) == 0 || (self.prg_ram_protect & PRG_RAM_WRITE_PROTECT) != 0 {
+                return;
+            }
+            let len = self.cartridge.prg_ram.len();
+            self.cartridge.prg_ram[(addr - 0x6000) as usize % len] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = chr_bank_1k(self, addr);
+        return read_chr_bank(&self.cartridge, 0x0400, bank, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = chr_bank_1k(self, addr);
+        write_chr_bank(&mut self.cartridge, 0x0400, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        return self.mirroring;
+    }
+
+    fn ppu_bus_address(&mut self, addr: u16) {
+        let a12 = (addr & 0x1000) != 0;
+        if a12 && !self.a12_high {
+            // short low pulses (the nametable fetches between sprite fetches) are filtered out
+            if self.cycle - self.a12_low_cycle >= A12_FILTER_CYCLES {
+                clock_irq_counter(self);
+            }
+        } else if !a12 && self.a12_high {
+            self.a12_low_cycle = self.cycle;
+        }
+        self.a12_high = a12;
+    }
+
+    fn clock_cpu(&mut self) {
+        self.cycle += 1;
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        return self.irq;
+    }
+}
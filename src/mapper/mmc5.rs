@@ -0,0 +1,480 @@
+use super::super::apu::pulse;
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+use super::{PPU_FETCH_IDLE, PPU_FETCH_SPRITES, PPU_FETCH_BACKGROUND, AUDIO_PULSE_SCALE};
+
+// headers rarely give the PRG RAM size; 64KB covers every board
+const PRG_RAM_SIZE: usize = 0x10000;
+
+// $5104
+const EXRAM_NAMETABLE: u8 = 0;
+const EXRAM_EXTENDED_ATTRIBUTE: u8 = 1;
+const EXRAM_READ_ONLY: u8 = 3;
+
+// $5105, two bits per nametable
+const NAMETABLE_CIRAM_0: u8 = 0;
+const NAMETABLE_CIRAM_1: u8 = 1;
+const NAMETABLE_EXRAM: u8 = 2;
+
+// $5200
+const SPLIT_ENABLE: u8 = 0x80;
+const SPLIT_RIGHT: u8 = 0x40;
+
+// the audio length counters and envelopes run from a fixed ~240Hz timer
+const AUDIO_FRAME_CYCLES: u32 = 7457;
+// linear mixing of the 8-bit PCM level
+const AUDIO_PCM_SCALE: f32 = 0.00168;
+
+// mapper 5
+pub struct Mmc5 {
+    cartridge: Cartridge,
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    // $5113-$5117
+    prg_banks: [u8; 5],
+    // $5120-$5127 (sprites in 8x16 mode) and $5128-$512B (background in 8x16 mode)
+    chr_banks_a: [u16; 8],
+    chr_banks_b: [u16; 4],
+    chr_upper: u8,
+    last_chr_set_b: bool,
+    exram: Vec<u8>,
+    exram_mode: u8,
+    nametable_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    multiplicand: u8,
+    multiplier: u8,
+    // what the PPU is fetching, followed through ppu_fetch_phase
+    fetch_phase: u8,
+    tall_sprites: bool,
+    in_frame: bool,
+    scanline: u16,
+    // background tile being fetched on the current line, 0-33
+    tile: u16,
+    tile_in_split: bool,
+    tile_exram: u8,
+    pulse1: pulse::Pulse,
+    pulse2: pulse::Pulse,
+    pcm: u8,
+    pcm_read_mode: bool,
+    pcm_irq_enabled: bool,
+    pcm_irq: bool,
+    audio_cycle: u32,
+    odd_cycle: bool,
+}
+
+pub fn new_mmc5(cartridge: Cartridge) -> Mmc5 {
+    let mut cartridge = cartridge;
+    if cartridge.prg_ram.len() < PRG_RAM_SIZE {
+        cartridge.prg_ram.resize(PRG_RAM_SIZE, 0);
+    }
+    return Mmc5 {
+        cartridge: cartridge,
+        prg_mode: 3,
+        chr_mode: 0,
+        prg_ram_protect: [0, 0],
+        prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+        chr_banks_a: [0; 8],
+        chr_banks_b: [0; 4],
+        chr_upper: 0,
+        last_chr_set_b: false,
+        exram: vec![0; 0x400],
+        exram_mode: 0,
+        nametable_mapping: 0,
+        fill_tile: 0,
+        fill_attribute: 0,
+        split_control: 0,
+        split_scroll: 0,
+        split_bank: 0,
+        irq_compare: 0,
+        irq_enabled: false,
+        irq_pending: false,
+        multiplicand: 0xFF,
+        multiplier: 0xFF,
+        fetch_phase: PPU_FETCH_IDLE,
+        tall_sprites: false,
+        in_frame: false,
+        scanline: 0,
+        tile: 0,
+        tile_in_split: false,
+        tile_exram: 0,
+        pulse1: pulse::new_pulse_without_sweep(),
+        pulse2: pulse::new_pulse_without_sweep(),
+        pcm: 0,
+        pcm_read_mode: false,
+        pcm_irq_enabled: false,
+        pcm_irq: false,
+        audio_cycle: 0,
+        odd_cycle: false,
+    };
+}
+
+// returns (rom, 8KB bank) for $6000-$FFFF
+fn prg_bank_8k(mmc5: &Mmc5, addr: u16) -> (bool, usize) {
+    if addr < 0x8000 {
+        return (false, (mmc5.prg_banks[0] & 0x07) as usize);
+    }
+    let slot = ((addr - 0x8000) >> 13) as u8;
+    // register index into prg_banks and how many 8KB slots it covers
+    let (register, size) = match (mmc5.prg_mode & 0x03, slot) {
+        (0, _) => (4, 4),
+        (1, 0) | (1, 1) => (2, 2),
+        (1, _) => (4, 2),
+        (2, 0) | (2, 1) => (2, 2),
+        (2, 2) => (3, 1),
+        (2, _) => (4, 1),
+        (_, slot) => (1 + slot as usize, 1),
+    };
+    let value = mmc5.prg_banks[register];
+    // $5117 always maps ROM, the others pick with bit 7
+    let rom = register == 4 || (value & 0x80) != 0;
+    let bank = ((value & 0x7F) as usize & !(size - 1)) + (slot as usize & (size - 1));
+    if rom {
+        return (true, bank);
+    }
+    return (false, bank & 0x07);
+}
+
+fn prg_ram_index(bank: usize, addr: u16) -> usize {
+    return (bank * 0x2000 + (addr & 0x1FFF) as usize) % PRG_RAM_SIZE;
+}
+
+fn is_prg_ram_writable(mmc5: &Mmc5) -> bool {
+    return mmc5.prg_ram_protect[0] == 0x02 && mmc5.prg_ram_protect[1] == 0x01;
+}
+
+fn chr_bank_1k(mmc5: &Mmc5, addr: u16) -> usize {
+    // 8x16 sprites fetch through set A and the background through set B,
+    // otherwise whichever set was written last is used for everything
+    let set_b = if mmc5.tall_sprites {
+        mmc5.fetch_phase != PPU_FETCH_SPRITES
+    } else {
+        mmc5.last_chr_set_b
+    };
+    let slot = ((addr >> 10) & 0x07) as usize;
+    let (value, size) = match (mmc5.chr_mode & 0x03, set_b) {
+        (0, false) => (mmc5.chr_banks_a[7], 8),
+        (0, true) => (mmc5.chr_banks_b[3], 8),
+        (1, false) => (mmc5.chr_banks_a[(slot & 4) + 3], 4),
+        (1, true) => (mmc5.chr_banks_b[3], 4),
+        (2, false) => (mmc5.chr_banks_a[(slot & 6) + 1], 2),
+        (2, true) => (mmc5.chr_banks_b[(slot & 2) + 1], 2),
+        (_, false) => (mmc5.chr_banks_a[slot], 1),
+        (_, true) => (mmc5.chr_banks_b[slot & 3], 1),
+    };
+    return (value as usize) * size + (slot & (size - 1));
+}
+
+fn is_background_fetch(mmc5: &Mmc5) -> bool {
+    return mmc5.in_frame && mmc5.fetch_phase == PPU_FETCH_BACKGROUND;
+}
+
+fn split_y(mmc5: &Mmc5) -> u16 {
+    return (mmc5.scanline + mmc5.split_scroll as u16) % 240;
+}
+
+fn is_tile_in_split(mmc5: &Mmc5) -> bool {
+    if (mmc5.split_control & SPLIT_ENABLE) == 0 || mmc5.exram_mode > EXRAM_EXTENDED_ATTRIBUTE {
+        return false;
+    }
+    let threshold = (mmc5.split_control & 0x1F) as u16;
+    if (mmc5.split_control & SPLIT_RIGHT) != 0 {
+        return mmc5.tile >= threshold;
+    }
+    return mmc5.tile < threshold;
+}
+
+// one attribute byte carrying the same palette for all four quadrants
+fn attribute_byte(palette: u8) -> u8 {
+    return (palette & 0x03) * 0x55;
+}
+
+fn write_chr_register(mmc5: &mut Mmc5, addr: u16, value: u8) {
+    let value = (value as u16) | ((mmc5.chr_upper as u16) << 8);
+    if addr < 0x5128 {
+        mmc5.chr_banks_a[(addr - 0x5120) as usize] = value;
+        mmc5.last_chr_set_b = false;
+    } else {
+        mmc5.chr_banks_b[(addr - 0x5128) as usize] = value;
+        mmc5.last_chr_set_b = true;
+    }
+}
+
+fn write_audio(mmc5: &mut Mmc5, addr: u16, value: u8) {
+    match addr {
+        // the MMC5 pulses have no sweep unit, so $5001/$5005 do nothing
+        0x5001 | 0x5005 => {}
+        0x5000..=0x5003 => pulse::write_register(&mut mmc5.pulse1, addr - 0x5000, value),
+        0x5004..=0x5007 => pulse::write_register(&mut mmc5.pulse2, addr - 0x5004, value),
+        0x5010 => {
+            mmc5.pcm_read_mode = (value & 0x01) != 0;
+            mmc5.pcm_irq_enabled = (value & 0x80) != 0;
+        }
+        0x5011 => {
+            // a zero write is ignored
+            if !mmc5.pcm_read_mode && value != 0 {
+                mmc5.pcm = value;
+            }
+        }
+        0x5015 => {
+            pulse::set_enabled(&mut mmc5.pulse1, (value & 0x01) != 0);
+            pulse::set_enabled(&mut mmc5.pulse2, (value & 0x02) != 0);
+        }
+        _ => {}
+    }
+}
+
+fn write_register(mmc5: &mut Mmc5, addr: u16, value: u8) {
+    match addr {
+        0x5000..=0x5015 => write_audio(mmc5, addr, value),
+        0x5100 => mmc5.prg_mode = value & 0x03,
+        0x5101 => mmc5.chr_mode = value & 0x03,
+        0x5102 => mmc5.prg_ram_protect[0] = value & 0x03,
+        0x5103 => mmc5.prg_ram_protect[1] = value & 0x03,
+        0x5104 => mmc5.exram_mode = value & 0x03,
+        0x5105 => mmc5.nametable_mapping = value,
+        0x5106 => mmc5.fill_tile = value,
+        0x5107 => mmc5.fill_attribute = value & 0x03,
+        0x5113..=0x5117 => mmc5.prg_banks[(addr - 0x5113) as usize] = value,
+        0x5120..=0x512B => write_chr_register(mmc5, addr, value),
+        0x5130 => mmc5.chr_upper = value & 0x03,
+        0x5200 => mmc5.split_control = value,
+        0x5201 => mmc5.split_scroll = value,
+        0x5202 => mmc5.split_bank = value,
+        0x5203 => mmc5.irq_compare = value,
+        0x5204 => mmc5.irq_enabled = (value & 0x80) != 0,
+        0x5205 => mmc5.multiplicand = value,
+        0x5206 => mmc5.multiplier = value,
+        0x5C00..=0x5FFF => {
+            match mmc5.exram_mode {
+                // while not rendering the nametable modes store 0
+                EXRAM_NAMETABLE | EXRAM_EXTENDED_ATTRIBUTE => {
+                    mmc5.exram[(addr - 0x5C00) as usize] = if mmc5.in_frame { value } else { 0 };
+                }
+                EXRAM_READ_ONLY => {}
+                _ => mmc5.exram[(addr - 0x5C00) as usize] = value,
+            }
+        }
+        _ => {}
+    }
+}
+
+fn read_register(mmc5: &mut Mmc5, addr: u16) -> Option<u8> {
+    match addr {
+        0x5010 => {
+            let value = (if mmc5.pcm_irq { 0x80 } else { 0 }) | (if mmc5.pcm_read_mode { 0x01 } else { 0 });
+            mmc5.pcm_irq = false;
+            return Some(value);
+        }
+        0x5015 => {
+            let value = (if pulse::is_length_active(&mmc5.pulse1) { 0x01 } else { 0 })
+                | (if pulse::is_length_active(&mmc5.pulse2) { 0x02 } else { 0 });
+            return Some(value);
+        }
+        0x5204 => {
+            let value = (if mmc5.irq_pending { 0x80 } else { 0 }) | (if mmc5.in_frame { 0x40 } else { 0 });
+            mmc5.irq_pending = false;
+            return Some(value);
+        }
+        0x5205 => return Some(((mmc5.multiplicand as u16) * (mmc5.multiplier as u16)) as u8),
+        0x5206 => return Some((((mmc5.multiplicand as u16) * (mmc5.multiplier as u16)) >> 8) as u8),
+        0x5C00..=0x5FFF => {
+            if mmc5.exram_mode <= EXRAM_EXTENDED_ATTRIBUTE {
+                return None;
+            }
+            return Some(mmc5.exram[(addr - 0x5C00) as usize]);
+        }
+        _ => return None,
+    }
+}
+
+fn read_mapped_nametable(mmc5: &Mmc5, addr: u16, vram: &[u8]) -> u8 {
+    let offset = (addr & 0x03FF) as usize;
+    let quadrant = (addr >> 10) & 0x03;
+    match (mmc5.nametable_mapping >> (quadrant * 2)) & 0x03 {
+        NAMETABLE_CIRAM_0 => return vram[offset],
+        NAMETABLE_CIRAM_1 => return vram[0x400 + offset],
+        NAMETABLE_EXRAM => {
+            if mmc5.exram_mode > EXRAM_EXTENDED_ATTRIBUTE {
+                return 0;
+            }
+            return mmc5.exram[offset];
+        }
+        _ => {
+            // fill mode
+            if offset >= 0x3C0 {
+                return attribute_byte(mmc5.fill_attribute);
+            }
+            return mmc5.fill_tile;
+        }
+    }
+}
+
+fn clock_audio(mmc5: &mut Mmc5) {
+    // pulse timers run at the APU rate
+    mmc5.odd_cycle = !mmc5.odd_cycle;
+    if mmc5.odd_cycle {
+        pulse::clock_timer(&mut mmc5.pulse1);
+        pulse::clock_timer(&mut mmc5.pulse2);
+    }
+    mmc5.audio_cycle += 1;
+    if mmc5.audio_cycle >= AUDIO_FRAME_CYCLES {
+        mmc5.audio_cycle = 0;
+        pulse::clock_envelope(&mut mmc5.pulse1);
+        pulse::clock_envelope(&mut mmc5.pulse2);
+        pulse::clock_length_and_sweep(&mut mmc5.pulse1);
+        pulse::clock_length_and_sweep(&mut mmc5.pulse2);
+    }
+}
+
+impl Mapper for Mmc5 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr < 0x6000 {
+            return read_register(self, addr);
+        }
+        let (rom, bank) = prg_bank_8k(self, addr);
+        let value = if rom {
+            read_prg_bank(&self.cartridge, 0x2000, bank, addr)
+        } else {
+            self.cartridge.prg_ram[prg_ram_index(bank, addr)]
+        };
+        if self.pcm_read_mode && addr >= 0x8000 && addr < 0xC000 {
+            // in read mode the PCM channel samples the CPU data bus; a zero raises the IRQ
+            if value == 0 {
+                self.pcm_irq = true;
+            } else {
+                self.pcm = value;
+            }
+        }
+        return Some(value);
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr < 0x6000 {
+            write_register(self, addr, value);
+            return;
+        }
+        let (rom, bank) = prg_bank_8k(self, addr);
+        if !rom && is_prg_ram_writable(self) {
+            self.cartridge.prg_ram[prg_ram_index(bank, addr)] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        if is_background_fetch(self) {
+            if self.tile_in_split {
+                // the split has its own 4KB bank and vertical scroll
+                let addr = (addr & 0x0FF8) | (split_y(self) & 0x07);
+                return read_chr_bank(&self.cartridge, 0x1000, self.split_bank as usize, addr);
+            }
+            if self.exram_mode == EXRAM_EXTENDED_ATTRIBUTE {
+                // each tile picks its own 4KB bank
+                let bank = ((self.tile_exram & 0x3F) as usize) | ((self.chr_upper as usize) << 6);
+                return read_chr_bank(&self.cartridge, 0x1000, bank, addr);
+            }
+        }
+        let bank = chr_bank_1k(self, addr);
+        return read_chr_bank(&self.cartridge, 0x0400, bank, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = chr_bank_1k(self, addr);
+        write_chr_bank(&mut self.cartridge, 0x0400, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        // nametables are mapped through read_nametable/write_nametable instead
+        return ppu::Mirroring::Vertical;
+    }
+
+    fn read_nametable(&mut self, addr: u16, vram: &[u8]) -> u8 {
+        let offset = addr & 0x03FF;
+        if !is_background_fetch(self) {
+            return read_mapped_nametable(self, addr, vram);
+        }
+
+        if offset < 0x3C0 {
+            // a tile fetch starts; the attribute and pattern fetches that follow belong to it
+            self.tile_in_split = is_tile_in_split(self);
+            self.tile += 1;
+            if self.tile_in_split {
+                let column = (self.tile - 1) & 0x1F;
+                return self.exram[((split_y(self) / 8) * 32 + column) as usize];
+            }
+            self.tile_exram = self.exram[offset as usize];
+            return read_mapped_nametable(self, addr, vram);
+        }
+
+        if self.tile_in_split {
+            let column = (self.tile - 1) & 0x1F;
+            let y = split_y(self);
+            let attribute = self.exram[(0x3C0 + (y / 32) * 8 + column / 4) as usize];
+            let shift = ((y / 16) & 1) * 4 + ((column / 2) & 1) * 2;
+            return attribute_byte(attribute >> shift);
+        }
+        if self.exram_mode == EXRAM_EXTENDED_ATTRIBUTE {
+            return attribute_byte(self.tile_exram >> 6);
+        }
+        return read_mapped_nametable(self, addr, vram);
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, vram: &mut [u8]) {
+        let offset = (addr & 0x03FF) as usize;
+        let quadrant = (addr >> 10) & 0x03;
+        match (self.nametable_mapping >> (quadrant * 2)) & 0x03 {
+            NAMETABLE_CIRAM_0 => vram[offset] = value,
+            NAMETABLE_CIRAM_1 => vram[0x400 + offset] = value,
+            NAMETABLE_EXRAM => {
+                if self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTE {
+                    self.exram[offset] = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn ppu_fetch_phase(&mut self, phase: u8, line: u16, tall_sprites: bool) {
+        self.fetch_phase = phase;
+        self.tall_sprites = tall_sprites;
+        if phase == PPU_FETCH_IDLE {
+            self.in_frame = false;
+            return;
+        }
+        if phase != PPU_FETCH_BACKGROUND {
+            return;
+        }
+
+        // the line after the last visible one isn't rendered
+        if line >= 240 {
+            return;
+        }
+        self.tile = 0;
+        self.scanline = line;
+        if line == 0 {
+            self.in_frame = true;
+            self.irq_pending = false;
+        } else if line as u8 == self.irq_compare {
+            self.irq_pending = true;
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        clock_audio(self);
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        return (self.irq_pending && self.irq_enabled) || (self.pcm_irq && self.pcm_irq_enabled);
+    }
+
+    fn audio_output(&self) -> f32 {
+        let pulses = pulse::output(&self.pulse1) + pulse::output(&self.pulse2);
+        return (pulses as f32) * AUDIO_PULSE_SCALE + (self.pcm as f32) * AUDIO_PCM_SCALE;
+    }
+}
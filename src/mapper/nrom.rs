@@ -0,0 +1,44 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// mapper 0: 16KB or 32KB of PRG ROM, 8KB CHR, no banking
+pub struct Nrom {
+    cartridge: Cartridge,
+}
+
+pub fn new_nrom(cartridge: Cartridge) -> Nrom {
+    return Nrom {
+        cartridge: cartridge,
+    };
+}
+
+impl Mapper for Nrom {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            // NROM-128 mirrors its 16KB at $C000
+            return Some(read_prg_bank(&self.cartridge, 0x8000, 0, addr - 0x8000));
+        } else if addr >= 0x6000 {
+            // Family BASIC style PRG RAM
+            return Some(self.cartridge.prg_ram[(addr - 0x6000) as usize]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x6000 && addr < 0x8000 {
+            self.cartridge.prg_ram[(addr - 0x6000) as usize] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        return read_chr_bank(&self.cartridge, 0x2000, 0, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        write_chr_bank(&mut self.cartridge, 0x2000, 0, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        return self.cartridge.mirroring;
+    }
+}
@@ -0,0 +1,361 @@
+use std::f32::consts::PI;
+
+// the VRC7's sound core: a six channel, two operator FM synth derived from the YM2413 (OPLL)
+
+// built in instruments 1-15, as dumped from the VRC7 die; instrument 0 is the custom patch
+const PATCHES: [[u8; 8]; 15] = [
+    [0x03, 0x21, 0x05, 0x06, 0xE8, 0x81, 0x42, 0x27],
+    [0x13, 0x41, 0x14, 0x0D, 0xD8, 0xF6, 0x23, 0x12],
+    [0x11, 0x11, 0x08, 0x08, 0xFA, 0xB2, 0x20, 0x12],
+    [0x31, 0x61, 0x0C, 0x07, 0xA8, 0x64, 0x61, 0x27],
+    [0x32, 0x21, 0x1E, 0x06, 0xE1, 0x76, 0x01, 0x28],
+    [0x02, 0x01, 0x06, 0x00, 0xA3, 0xE2, 0xF4, 0xF4],
+    [0x21, 0x61, 0x1D, 0x07, 0x82, 0x81, 0x11, 0x07],
+    [0x23, 0x21, 0x22, 0x17, 0xA2, 0x72, 0x01, 0x17],
+    [0x35, 0x11, 0x25, 0x00, 0x40, 0x73, 0x72, 0x01],
+    [0xB5, 0x01, 0x0F, 0x0F, 0xA8, 0xA5, 0x51, 0x02],
+    [0x17, 0xC1, 0x24, 0x07, 0xF8, 0xF8, 0x22, 0x12],
+    [0x71, 0x23, 0x11, 0x06, 0x65, 0x74, 0x18, 0x16],
+    [0x01, 0x02, 0xD3, 0x05, 0xC9, 0x95, 0x03, 0x02],
+    [0x61, 0x63, 0x0C, 0x00, 0x94, 0xC0, 0x33, 0xF6],
+    [0x21, 0x72, 0x0D, 0x00, 0xC1, 0xD5, 0x56, 0x06],
+];
+
+// patch bytes 0 (modulator) and 1 (carrier)
+const PATCH_AM: u8 = 0x80;
+const PATCH_VIBRATO: u8 = 0x40;
+const PATCH_SUSTAINED: u8 = 0x20;
+const PATCH_KEY_SCALE_RATE: u8 = 0x10;
+const PATCH_MULTIPLIER: u8 = 0x0F;
+// patch byte 3
+const PATCH_CARRIER_HALF_SINE: u8 = 0x10;
+const PATCH_MODULATOR_HALF_SINE: u8 = 0x08;
+const PATCH_FEEDBACK: u8 = 0x07;
+
+// $20-$25
+const CHANNEL_SUSTAIN: u8 = 0x20;
+const CHANNEL_KEY_ON: u8 = 0x10;
+
+// one sample every 36 CPU cycles (the chip runs at twice the CPU clock and takes 72 clocks)
+const SAMPLE_CYCLES: u8 = 36;
+const SAMPLE_RATE: f32 = 49716.0;
+
+// frequency multipliers, doubled so 1/2 stays an integer
+const MULTIPLIERS: [u8; 16] = [1, 2, 4, 6, 8, 10, 12, 14, 16, 18, 20, 20, 24, 24, 30, 30];
+// key scale level attenuation (dB) by the top 4 F-number bits, for block 7 at 6dB per octave
+const KEY_SCALE_LEVELS: [f32; 16] = [
+    0.0, 18.0, 24.0, 27.75, 30.0, 32.25, 33.75, 35.25,
+    36.0, 37.5, 38.25, 39.0, 39.75, 40.5, 41.25, 42.0,
+];
+
+// the envelope spans 48dB; anything quieter is silent
+const ENVELOPE_RANGE: f32 = 48.0;
+// seconds to decay across the whole range, and to attack from silence, at rate 4
+const DECAY_TIME: f32 = 9.82;
+const ATTACK_TIME: f32 = 0.71;
+// rates used on key off for percussive patches, and with the channel sustain bit
+const RELEASE_RATE_PERCUSSIVE: u8 = 7;
+const RELEASE_RATE_SUSTAIN: u8 = 5;
+
+// tremolo: 3.7Hz, 4.8dB deep; vibrato: 6.4Hz, about 14 cents
+const AM_FREQUENCY: f32 = 3.7;
+const AM_DEPTH: f32 = 4.8;
+const VIBRATO_FREQUENCY: f32 = 6.4;
+const VIBRATO_DEPTH: f32 = 0.008;
+
+// a full scale modulator swings the carrier phase by 4 pi
+const MODULATION_INDEX: f32 = 4.0 * PI;
+// a full scale channel, on the same scale as apu::output
+const CHANNEL_SCALE: f32 = 0.06;
+
+const ENVELOPE_ATTACK: u8 = 0;
+const ENVELOPE_DECAY: u8 = 1;
+const ENVELOPE_SUSTAIN: u8 = 2;
+const ENVELOPE_RELEASE: u8 = 3;
+
+const MODULATOR: usize = 0;
+const CARRIER: usize = 1;
+
+struct Operator {
+    // fraction of a cycle
+    phase: f32,
+    envelope_state: u8,
+    // attenuation in dB
+    envelope: f32,
+}
+
+struct Channel {
+    fnum: u16,
+    block: u8,
+    sustain: bool,
+    key_on: bool,
+    instrument: u8,
+    volume: u8,
+    operators: [Operator; 2],
+    // the modulator's last two outputs, for self feedback
+    feedback: [f32; 2],
+}
+
+pub struct Opll {
+    address: u8,
+    custom_patch: [u8; 8],
+    channels: [Channel; 6],
+    // fractions of a cycle
+    am_phase: f32,
+    vibrato_phase: f32,
+    cycle: u8,
+    // held between samples
+    output: f32,
+}
+
+fn new_operator() -> Operator {
+    return Operator {
+        phase: 0.0,
+        envelope_state: ENVELOPE_RELEASE,
+        envelope: ENVELOPE_RANGE,
+    };
+}
+
+fn new_channel() -> Channel {
+    return Channel {
+        fnum: 0,
+        block: 0,
+        sustain: false,
+        key_on: false,
+        instrument: 0,
+        volume: 0,
+        operators: [new_operator(), new_operator()],
+        feedback: [0.0; 2],
+    };
+}
+
+pub fn new_opll() -> Opll {
+    return Opll {
+        address: 0,
+        custom_patch: [0; 8],
+        channels: [new_channel(), new_channel(), new_channel(), new_channel(), new_channel(), new_channel()],
+        am_phase: 0.0,
+        vibrato_phase: 0.0,
+        cycle: 0,
+        output: 0.0,
+    };
+}
+
+// silences every channel and clears the registers
+pub fn reset(opll: &mut Opll) {
+    *opll = new_opll();
+}
+
+pub fn write_address(opll: &mut Opll, value: u8) {
+    opll.address = value;
+}
+
+pub fn write_data(opll: &mut Opll, value: u8) {
+    let address = opll.address;
+    let index = (address & 0x0F) as usize;
+    match address & 0xF0 {
+        0x00 if index < 8 => opll.custom_patch[index] = value,
+        0x10 if index < 6 => {
+            let channel = &mut opll.channels[index];
+            channel.fnum = (channel.fnum & 0x100) | value as u16;
+        }
+        0x20 if index < 6 => {
+            let channel = &mut opll.channels[index];
+            channel.fnum = (channel.fnum & 0xFF) | (((value & 1) as u16) << 8);
+            channel.block = (value >> 1) & 0x07;
+            channel.sustain = (value & CHANNEL_SUSTAIN) != 0;
+            let key_on = (value & CHANNEL_KEY_ON) != 0;
+            if key_on && !channel.key_on {
+                for operator in channel.operators.iter_mut() {
+                    operator.phase = 0.0;
+                    operator.envelope_state = ENVELOPE_ATTACK;
+                }
+            } else if !key_on && channel.key_on {
+                for operator in channel.operators.iter_mut() {
+                    operator.envelope_state = ENVELOPE_RELEASE;
+                }
+            }
+            channel.key_on = key_on;
+        }
+        0x30 if index < 6 => {
+            let channel = &mut opll.channels[index];
+            channel.instrument = value >> 4;
+            channel.volume = value & 0x0F;
+        }
+        _ => {}
+    }
+}
+
+fn patch(opll: &Opll, channel: &Channel) -> [u8; 8] {
+    if channel.instrument == 0 {
+        return opll.custom_patch;
+    }
+    return PATCHES[(channel.instrument - 1) as usize];
+}
+
+// 0-63 including key scaling; 0 stops the envelope
+fn effective_rate(channel: &Channel, rate: u8, key_scale_rate: bool) -> u8 {
+    if rate == 0 {
+        return 0;
+    }
+    let key_scale = (channel.block << 1) | (channel.fnum >> 8) as u8;
+    let key_scale = if key_scale_rate { key_scale } else { key_scale >> 2 };
+    return (rate * 4 + key_scale).min(63);
+}
+
+// each step of 4 in the rate halves the time
+fn rate_time(time: f32, rate: u8) -> f32 {
+    return time / 2.0f32.powf((rate as f32 - 4.0) / 4.0);
+}
+
+fn clock_envelope(channel: &mut Channel, slot: usize, patch: &[u8; 8]) {
+    let attack = patch[4 + slot] >> 4;
+    let decay = patch[4 + slot] & 0x0F;
+    let sustain_level = (patch[6 + slot] >> 4) as f32 * 3.0;
+    let release = patch[6 + slot] & 0x0F;
+    let sustained = (patch[slot] & PATCH_SUSTAINED) != 0;
+    let key_scale_rate = (patch[slot] & PATCH_KEY_SCALE_RATE) != 0;
+
+    let state = channel.operators[slot].envelope_state;
+    let rate = match state {
+        ENVELOPE_ATTACK => attack,
+        ENVELOPE_DECAY => decay,
+        // percussive patches keep fading at the release rate while the key is held
+        ENVELOPE_SUSTAIN => if sustained { 0 } else { release },
+        _ => {
+            if channel.sustain {
+                RELEASE_RATE_SUSTAIN
+            } else if sustained {
+                release
+            } else {
+                RELEASE_RATE_PERCUSSIVE
+            }
+        }
+    };
+    let rate = effective_rate(channel, rate, key_scale_rate);
+    let operator = &mut channel.operators[slot];
+    if rate == 0 {
+        return;
+    }
+
+    if state == ENVELOPE_ATTACK {
+        // exponential approach, done in one step at the top rates
+        if rate >= 60 {
+            operator.envelope = 0.0;
+        } else {
+            let samples = rate_time(ATTACK_TIME, rate) * SAMPLE_RATE;
+            let factor = (ENVELOPE_RANGE + 1.0).powf(-1.0 / samples);
+            operator.envelope = (operator.envelope + 1.0) * factor - 1.0;
+        }
+        if operator.envelope <= 0.0 {
+            operator.envelope = 0.0;
+            operator.envelope_state = ENVELOPE_DECAY;
+        }
+        return;
+    }
+
+    let samples = rate_time(DECAY_TIME, rate) * SAMPLE_RATE;
+    operator.envelope = (operator.envelope + ENVELOPE_RANGE / samples).min(ENVELOPE_RANGE);
+    if state == ENVELOPE_DECAY && operator.envelope >= sustain_level {
+        operator.envelope = sustain_level;
+        operator.envelope_state = ENVELOPE_SUSTAIN;
+    }
+}
+
+// total attenuation (dB) of one operator
+fn attenuation(opll: &Opll, channel: &Channel, slot: usize, patch: &[u8; 8]) -> f32 {
+    let mut total = channel.operators[slot].envelope;
+    // modulator: total level in 0.75dB steps; carrier: channel volume in 3dB steps
+    if slot == MODULATOR {
+        total += (patch[2] & 0x3F) as f32 * 0.75;
+    } else {
+        total += channel.volume as f32 * 3.0;
+    }
+    let key_scale_level = patch[2 + slot] >> 6;
+    if key_scale_level != 0 {
+        let level = KEY_SCALE_LEVELS[(channel.fnum >> 5) as usize] - 6.0 * (7 - channel.block) as f32;
+        // 1.5, 3 and 6dB per octave
+        total += level.max(0.0) / (1 << (3 - key_scale_level)) as f32;
+    }
+    if (patch[slot] & PATCH_AM) != 0 {
+        total += (1.0 - (opll.am_phase * 2.0 * PI).cos()) * 0.5 * AM_DEPTH;
+    }
+    return total;
+}
+
+fn operator_output(phase: f32, offset: f32, half_sine: bool, attenuation: f32) -> f32 {
+    if attenuation >= ENVELOPE_RANGE {
+        return 0.0;
+    }
+    let wave = (phase * 2.0 * PI + offset).sin();
+    if half_sine && wave < 0.0 {
+        return 0.0;
+    }
+    return wave * 10.0f32.powf(-attenuation / 20.0);
+}
+
+fn clock_phase(channel: &mut Channel, slot: usize, patch: &[u8; 8], vibrato_phase: f32) {
+    // F-number * 2^(block - 1) * multiple / 2^18 cycles per sample
+    let increment = ((channel.fnum as u32) << channel.block) as f32 * MULTIPLIERS[(patch[slot] & PATCH_MULTIPLIER) as usize] as f32;
+    let mut increment = increment / (1 << 20) as f32;
+    if (patch[slot] & PATCH_VIBRATO) != 0 {
+        increment = increment * (1.0 + VIBRATO_DEPTH * (vibrato_phase * 2.0 * PI).sin());
+    }
+    let operator = &mut channel.operators[slot];
+    operator.phase = (operator.phase + increment).fract();
+}
+
+fn channel_output(opll: &mut Opll, index: usize) -> f32 {
+    let patch = patch(opll, &opll.channels[index]);
+    let modulator_attenuation = attenuation(opll, &opll.channels[index], MODULATOR, &patch);
+    let carrier_attenuation = attenuation(opll, &opll.channels[index], CARRIER, &patch);
+
+    let feedback = patch[3] & PATCH_FEEDBACK;
+    let channel = &opll.channels[index];
+    let feedback_offset = if feedback == 0 {
+        0.0
+    } else {
+        // pi/16 up to 4pi
+        (channel.feedback[0] + channel.feedback[1]) * 0.5 * PI * 2.0f32.powi(feedback as i32 - 5)
+    };
+    let modulator = operator_output(channel.operators[MODULATOR].phase, feedback_offset,
+        (patch[3] & PATCH_MODULATOR_HALF_SINE) != 0, modulator_attenuation);
+    let carrier = operator_output(channel.operators[CARRIER].phase, modulator * MODULATION_INDEX,
+        (patch[3] & PATCH_CARRIER_HALF_SINE) != 0, carrier_attenuation);
+
+    let channel = &mut opll.channels[index];
+    channel.feedback[1] = channel.feedback[0];
+    channel.feedback[0] = modulator;
+    return carrier;
+}
+
+fn clock_sample(opll: &mut Opll) {
+    opll.am_phase = (opll.am_phase + AM_FREQUENCY / SAMPLE_RATE).fract();
+    opll.vibrato_phase = (opll.vibrato_phase + VIBRATO_FREQUENCY / SAMPLE_RATE).fract();
+
+    let mut total = 0.0;
+    for index in 0..opll.channels.len() {
+        total += channel_output(opll, index);
+        let patch = patch(opll, &opll.channels[index]);
+        let vibrato_phase = opll.vibrato_phase;
+        let channel = &mut opll.channels[index];
+        for slot in 0..2 {
+            clock_phase(channel, slot, &patch, vibrato_phase);
+            clock_envelope(channel, slot, &patch);
+        }
+    }
+    opll.output = total * CHANNEL_SCALE;
+}
+
+// called once per CPU cycle
+pub fn clock(opll: &mut Opll) {
+    opll.cycle += 1;
+    if opll.cycle >= SAMPLE_CYCLES {
+        opll.cycle = 0;
+        clock_sample(opll);
+    }
+}
+
+pub fn output(opll: &Opll) -> f32 {
+    return opll.output;
+}
@@ -0,0 +1,52 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+
+// mapper 2: switchable 16KB at $8000, last 16KB fixed at $C000
+pub struct Uxrom {
+    cartridge: Cartridge,
+    prg_bank: u8,
+    // the ROM drives the data bus during writes too, so the written value is ANDed with it
+    bus_conflicts: bool,
+}
+
+pub fn new_uxrom(cartridge: Cartridge, bus_conflicts: bool) -> Uxrom {
+    return Uxrom {
+        cartridge: cartridge,
+        prg_bank: 0,
+        bus_conflicts: bus_conflicts,
+    };
+}
+
+impl Mapper for Uxrom {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0xC000 {
+            let last = (self.cartridge.prg_rom.len() / 0x4000).max(1) - 1;
+            return Some(read_prg_bank(&self.cartridge, 0x4000, last, addr));
+        } else if addr >= 0x8000 {
+            return Some(read_prg_bank(&self.cartridge, 0x4000, self.prg_bank as usize, addr));
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            let mut value = value;
+            if self.bus_conflicts {
+                value = value & self.read_prg(addr).unwrap_or(0xFF);
+            }
+            self.prg_bank = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        return read_chr_bank(&self.cartridge, 0x2000, 0, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        write_chr_bank(&mut self.cartridge, 0x2000, 0, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        return self.cartridge.mirroring;
+    }
+}
@@ -0,0 +1,180 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+use super::vrc_irq;
+
+// VRC4 $9002: PRG swap mode
+const PRG_SWAP_MODE: u8 = 0x02;
+
+// mappers 21, 22, 23 and 25: VRC2 and VRC4 boards, which route different CPU address lines
+// to the chip's two register select pins
+pub struct Vrc4 {
+    cartridge: Cartridge,
+    // address lines feeding register select bits 0 and 1; several lines when the board is unknown
+    select_0: u16,
+    select_1: u16,
+    // VRC2 has no IRQ, no PRG swap mode and only H/V mirroring
+    vrc2: bool,
+    // VRC2a leaves CHR A10 unconnected, so bank numbers drop their low bit
+    chr_shift: u8,
+    prg_banks: [u8; 2],
+    prg_mode: u8,
+    chr_banks: [u16; 8],
+    mirroring: u8,
+    irq: vrc_irq::VrcIrq,
+}
+
+// select lines (bit 0, bit 1) and VRC2 flag for each mapper and NES 2.0 submapper
+fn wiring(mapper: u16, submapper: u8) -> (u16, u16, bool) {
+    match (mapper, submapper) {
+        // VRC4a: A1, A2
+        (21, 1) => (0x02, 0x04, false),
+        // VRC4c: A6, A7
+        (21, 2) => (0x40, 0x80, false),
+        (21, _) => (0x42, 0x84, false),
+        // VRC2a: A1, A0
+        (22, _) => (0x02, 0x01, true),
+        // VRC4f: A0, A1
+        (23, 1) => (0x01, 0x02, false),
+        // VRC4e: A2, A3
+        (23, 2) => (0x04, 0x08, false),
+        // VRC2b: A0, A1
+        (23, 3) => (0x01, 0x02, true),
+        (23, _) => (0x05, 0x0A, false),
+        // VRC4b: A1, A0
+        (25, 1) => (0x02, 0x01, false),
+        // VRC4d: A3, A2
+        (25, 2) => (0x08, 0x04, false),
+        // VRC2c: A1, A0
+        (25, 3) => (0x02, 0x01, true),
+        _ => (0x0A, 0x05, false),
+    }
+}
+
+pub fn new_vrc4(cartridge: Cartridge, mapper: u16, submapper: u8) -> Vrc4 {
+    let (select_0, select_1, vrc2) = wiring(mapper, submapper);
+    let mirroring = if cartridge.mirroring == ppu::Mirroring::Horizontal { 1 } else { 0 };
+    return Vrc4 {
+        cartridge: cartridge,
+        select_0: select_0,
+        select_1: select_1,
+        vrc2: vrc2,
+        chr_shift: if mapper == 22 { 1 } else { 0 },
+        prg_banks: [0, 1],
+        prg_mode: 0,
+        chr_banks: [0; 8],
+        mirroring: mirroring,
+        irq: vrc_irq::new_vrc_irq(),
+    };
+}
+
+// register select bits from whichever address lines the board wires to them
+fn register_select(vrc: &Vrc4, addr: u16) -> u16 {
+    let bit_0 = if (addr & vrc.select_0) != 0 { 1 } else { 0 };
+    let bit_1 = if (addr & vrc.select_1) != 0 { 2 } else { 0 };
+    return bit_0 | bit_1;
+}
+
+fn prg_bank_8k(vrc: &Vrc4, addr: u16) -> usize {
+    let second_last = (vrc.cartridge.prg_rom.len() / 0x2000).max(2) - 2;
+    let swapped = (vrc.prg_mode & PRG_SWAP_MODE) != 0;
+    match (addr >> 13) & 0x03 {
+        0 => if swapped { second_last } else { (vrc.prg_banks[0] & 0x1F) as usize },
+        1 => (vrc.prg_banks[1] & 0x1F) as usize,
+        2 => if swapped { (vrc.prg_banks[0] & 0x1F) as usize } else { second_last },
+        _ => second_last + 1,
+    }
+}
+
+fn chr_bank_1k(vrc: &Vrc4, addr: u16) -> usize {
+    return (vrc.chr_banks[(addr >> 10) as usize] >> vrc.chr_shift) as usize;
+}
+
+// $B000-$E003: each 1KB CHR bank is written a nibble at a time, low nibble first
+fn write_chr_register(vrc: &mut Vrc4, addr: u16, select: u16, value: u8) {
+    let slot = (((addr - 0xB000) >> 12) * 2 + (select >> 1)) as usize;
+    let bank = vrc.chr_banks[slot];
+    if (select & 1) == 0 {
+        vrc.chr_banks[slot] = (bank & 0x1F0) | (value & 0x0F) as u16;
+    } else {
+        let high_mask = if vrc.vrc2 { 0x0F } else { 0x1F };
+        vrc.chr_banks[slot] = (bank & 0x0F) | (((value & high_mask) as u16) << 4);
+    }
+}
+
+fn write_register(vrc: &mut Vrc4, addr: u16, value: u8) {
+    let select = register_select(vrc, addr);
+    match addr & 0xF000 {
+        0x8000 => vrc.prg_banks[0] = value,
+        0x9000 => {
+            if vrc.vrc2 {
+                vrc.mirroring = value & 0x01;
+            } else if select < 2 {
+                vrc.mirroring = value & 0x03;
+            } else {
+                vrc.prg_mode = value;
+            }
+        }
+        0xA000 => vrc.prg_banks[1] = value,
+        0xB000 | 0xC000 | 0xD000 | 0xE000 => write_chr_register(vrc, addr, select, value),
+        _ => {
+            if vrc.vrc2 {
+                return;
+            }
+            match select {
+                0 => vrc_irq::write_latch_low(&mut vrc.irq, value),
+                1 => vrc_irq::write_latch_high(&mut vrc.irq, value),
+                2 => vrc_irq::write_control(&mut vrc.irq, value),
+                _ => vrc_irq::acknowledge(&mut vrc.irq),
+            }
+        }
+    }
+}
+
+impl Mapper for Vrc4 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            let bank = prg_bank_8k(self, addr);
+            return Some(read_prg_bank(&self.cartridge, 0x2000, bank, addr));
+        } else if addr >= 0x6000 {
+            let len = self.cartridge.prg_ram.len();
+            return Some(self.cartridge.prg_ram[(addr - 0x6000) as usize % len]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            write_register(self, addr, value);
+        } else if addr >= 0x6000 {
+            let len = self.cartridge.prg_ram.len();
+            self.cartridge.prg_ram[(addr - 0x6000) as usize % len] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = chr_bank_1k(self, addr);
+        return read_chr_bank(&self.cartridge, 0x0400, bank, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = chr_bank_1k(self, addr);
+        write_chr_bank(&mut self.cartridge, 0x0400, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        match self.mirroring {
+            0 => ppu::Mirroring::Vertical,
+            1 => ppu::Mirroring::Horizontal,
+            2 => ppu::Mirroring::SingleScreenLower,
+            _ => ppu::Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        vrc_irq::clock(&mut self.irq);
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        return vrc_irq::is_pending(&self.irq);
+    }
+}
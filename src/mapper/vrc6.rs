@@ -0,0 +1,298 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank, AUDIO_PULSE_SCALE};
+use super::vrc_irq;
+
+// $B003: banking mode, mirroring and PRG RAM enable
+const CONTROL_CHR_MODE: u8 = 0x03;
+const CONTROL_MIRRORING: u8 = 0x0C;
+const CONTROL_PRG_RAM_ENABLE: u8 = 0x80;
+
+// $9003: halt and frequency scaling for all three channels
+const AUDIO_HALT: u8 = 0x01;
+const AUDIO_FREQUENCY_16X: u8 = 0x02;
+const AUDIO_FREQUENCY_256X: u8 = 0x04;
+
+// channel enable, in the third register of each channel
+const CHANNEL_ENABLE: u8 = 0x80;
+// pulse $x000: ignore duty, output the volume constantly
+const PULSE_DIGITIZED: u8 = 0x80;
+
+struct Vrc6Pulse {
+    control: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    step: u8,
+}
+
+struct Vrc6Saw {
+    rate: u8,
+    period: u16,
+    enabled: bool,
+    timer: u16,
+    // the accumulator adds on every other clock, 14 clocks per cycle
+    step: u8,
+    accumulator: u8,
+}
+
+// mappers 24 (VRC6a) and 26 (VRC6b, with A0 and A1 swapped)
+pub struct Vrc6 {
+    cartridge: Cartridge,
+    swap_select: bool,
+    prg_bank_16k: u8,
+    prg_bank_8k: u8,
+    chr_banks: [u8; 8],
+    control: u8,
+    irq: vrc_irq::VrcIrq,
+    audio_control: u8,
+    pulse1: Vrc6Pulse,
+    pulse2: Vrc6Pulse,
+    saw: Vrc6Saw,
+}
+
+fn new_vrc6_pulse() -> Vrc6Pulse {
+    return Vrc6Pulse {
+        control: 0,
+        period: 0,
+        enabled: false,
+        timer: 0,
+        step: 0,
+    };
+}
+
+fn new_vrc6_saw() -> Vrc6Saw {
+    return Vrc6Saw {
+        rate: 0,
+        period: 0,
+        enabled: false,
+        timer: 0,
+        step: 0,
+        accumulator: 0,
+    };
+}
+
+fn new_board(cartridge: Cartridge, swap_select: bool) -> Vrc6 {
+    let mirroring = if cartridge.mirroring == ppu::Mirroring::Horizontal { 0x04 } else { 0x00 };
+    return Vrc6 {
+        cartridge: cartridge,
+        swap_select: swap_select,
+        prg_bank_16k: 0,
+        prg_bank_8k: 0,
+        chr_banks: [0; 8],
+        control: mirroring | CONTROL_PRG_RAM_ENABLE,
+        irq: vrc_irq::new_vrc_irq(),
+        audio_control: 0,
+        pulse1: new_vrc6_pulse(),
+        pulse2: new_vrc6_pulse(),
+        saw: new_vrc6_saw(),
+    };
+}
+
+pub fn new_vrc6a(cartridge: Cartridge) -> Vrc6 {
+    return new_board(cartridge, false);
+}
+
+pub fn new_vrc6b(cartridge: Cartridge) -> Vrc6 {
+    return new_board(cartridge, true);
+}
+
+fn prg_bank_8k(vrc6: &Vrc6, addr: u16) -> usize {
+    match (addr >> 13) & 0x03 {
+        0 | 1 => ((vrc6.prg_bank_16k & 0x0F) as usize) * 2 + ((addr >> 13) & 1) as usize,
+        2 => (vrc6.prg_bank_8k & 0x1F) as usize,
+        _ => (vrc6.cartridge.prg_rom.len() / 0x2000).max(1) - 1,
+    }
+}
+
+fn chr_bank_1k(vrc6: &Vrc6, addr: u16) -> usize {
+    let slot = (addr >> 10) as usize;
+    match vrc6.control & CONTROL_CHR_MODE {
+        // eight 1KB banks
+        0 => vrc6.chr_banks[slot] as usize,
+        // four 2KB banks from R0-R3
+        1 => (vrc6.chr_banks[slot >> 1] as usize) * 2 + (slot & 1),
+        // 1KB banks R0-R3, then 2KB banks R4-R5
+        _ => {
+            if slot < 4 {
+                vrc6.chr_banks[slot] as usize
+            } else {
+                (vrc6.chr_banks[4 + ((slot - 4) >> 1)] as usize) * 2 + (slot & 1)
+            }
+        }
+    }
+}
+
+fn write_pulse(pulse: &mut Vrc6Pulse, select: u16, value: u8) {
+    match select {
+        0 => pulse.control = value,
+        1 => pulse.period = (pulse.period & 0x0F00) | value as u16,
+        _ => {
+            pulse.period = (pulse.period & 0x00FF) | (((value & 0x0F) as u16) << 8);
+            pulse.enabled = (value & CHANNEL_ENABLE) != 0;
+            // disabling restarts the duty cycle
+            if !pulse.enabled {
+                pulse.step = 0;
+            }
+        }
+    }
+}
+
+fn write_saw(saw: &mut Vrc6Saw, select: u16, value: u8) {
+    match select {
+        0 => saw.rate = value & 0x3F,
+        1 => saw.period = (saw.period & 0x0F00) | value as u16,
+        _ => {
+            saw.period = (saw.period & 0x00FF) | (((value & 0x0F) as u16) << 8);
+            saw.enabled = (value & CHANNEL_ENABLE) != 0;
+            if !saw.enabled {
+                saw.step = 0;
+                saw.accumulator = 0;
+            }
+        }
+    }
+}
+
+fn write_register(vrc6: &mut Vrc6, addr: u16, value: u8) {
+    // VRC6b swaps the two register select lines
+    let select = if vrc6.swap_select { ((addr & 1) << 1) | ((addr >> 1) & 1) } else { addr & 0x03 };
+    match (addr & 0xF000, select) {
+        (0x8000, _) => vrc6.prg_bank_16k = value,
+        (0x9000, 3) => vrc6.audio_control = value,
+        (0x9000, _) => write_pulse(&mut vrc6.pulse1, select, value),
+        (0xA000, 3) => {}
+        (0xA000, _) => write_pulse(&mut vrc6.pulse2, select, value),
+        (0xB000, 3) => vrc6.control = value,
+        (0xB000, _) => write_saw(&mut vrc6.saw, select, value),
+        (0xC000, _) => vrc6.prg_bank_8k = value,
+        (0xD000, _) => vrc6.chr_banks[select as usize] = value,
+        (0xE000, _) => vrc6.chr_banks[4 + select as usize] = value,
+        (_, 0) => vrc_irq::write_latch(&mut vrc6.irq, value),
+        (_, 1) => vrc_irq::write_control(&mut vrc6.irq, value),
+        (_, 2) => vrc_irq::acknowledge(&mut vrc6.irq),
+        _ => {}
+    }
+}
+
+fn period_shift(vrc6: &Vrc6) -> u16 {
+    if (vrc6.audio_control & AUDIO_FREQUENCY_256X) != 0 {
+        return 8;
+    } else if (vrc6.audio_control & AUDIO_FREQUENCY_16X) != 0 {
+        return 4;
+    }
+    return 0;
+}
+
+fn clock_pulse(pulse: &mut Vrc6Pulse, shift: u16) {
+    if !pulse.enabled {
+        return;
+    }
+    if pulse.timer == 0 {
+        pulse.timer = pulse.period >> shift;
+        pulse.step = (pulse.step + 1) & 0x0F;
+    } else {
+        pulse.timer -= 1;
+    }
+}
+
+fn clock_saw(saw: &mut Vrc6Saw, shift: u16) {
+    if !saw.enabled {
+        return;
+    }
+    if saw.timer > 0 {
+        saw.timer -= 1;
+        return;
+    }
+    saw.timer = saw.period >> shift;
+    saw.step += 1;
+    if saw.step == 14 {
+        saw.step = 0;
+        saw.accumulator = 0;
+    } else if (saw.step & 1) == 0 {
+        saw.accumulator = saw.accumulator.wrapping_add(saw.rate);
+    }
+}
+
+fn clock_audio(vrc6: &mut Vrc6) {
+    if (vrc6.audio_control & AUDIO_HALT) != 0 {
+        return;
+    }
+    // the timers run at the full CPU rate
+    let shift = period_shift(vrc6);
+    clock_pulse(&mut vrc6.pulse1, shift);
+    clock_pulse(&mut vrc6.pulse2, shift);
+    clock_saw(&mut vrc6.saw, shift);
+}
+
+fn pulse_output(pulse: &Vrc6Pulse) -> u8 {
+    if !pulse.enabled {
+        return 0;
+    }
+    let duty = (pulse.control >> 4) & 0x07;
+    if (pulse.control & PULSE_DIGITIZED) != 0 || pulse.step <= duty {
+        return pulse.control & 0x0F;
+    }
+    return 0;
+}
+
+fn saw_output(saw: &Vrc6Saw) -> u8 {
+    if !saw.enabled {
+        return 0;
+    }
+    // the top 5 bits reach the DAC
+    return saw.accumulator >> 3;
+}
+
+impl Mapper for Vrc6 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            let bank = prg_bank_8k(self, addr);
+            return Some(read_prg_bank(&self.cartridge, 0x2000, bank, addr));
+        } else if addr >= 0x6000 && (self.control & CONTROL_PRG_RAM_ENABLE) != 0 {
+            let len = self.cartridge.prg_ram.len();
+            return Some(self.cartridge.prg_ram[(addr - 0x6000) as usize % len]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            write_register(self, addr, value);
+        } else if addr >= 0x6000 && (self.control & CONTROL_PRG_RAM_ENABLE) != 0 {
+            let len = self.cartridge.prg_ram.len();
+            self.cartridge.prg_ram[(addr - 0x6000) as usize % len] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = chr_bank_1k(self, addr);
+        return read_chr_bank(&self.cartridge, 0x0400, bank, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = chr_bank_1k(self, addr);
+        write_chr_bank(&mut self.cartridge, 0x0400, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        match (self.control & CONTROL_MIRRORING) >> 2 {
+            0 => ppu::Mirroring::Vertical,
+            1 => ppu::Mirroring::Horizontal,
+            2 => ppu::Mirroring::SingleScreenLower,
+            _ => ppu::Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        vrc_irq::clock(&mut self.irq);
+        clock_audio(self);
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        return vrc_irq::is_pending(&self.irq);
+    }
+
+    fn audio_output(&self) -> f32 {
+        let total = pulse_output(&self.pulse1) as u16 + pulse_output(&self.pulse2) as u16 + saw_output(&self.saw) as u16;
+        return (total as f32) * AUDIO_PULSE_SCALE;
+    }
+}
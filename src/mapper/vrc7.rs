@@ -0,0 +1,127 @@
+use super::super::ppu;
+use super::{Mapper, Cartridge, read_prg_bank, read_chr_bank, write_chr_bank};
+use super::vrc_irq;
+use super::opll;
+
+// $E000: mirroring, audio reset and PRG RAM enable
+const CONTROL_MIRRORING: u8 = 0x03;
+const CONTROL_AUDIO_RESET: u8 = 0x40;
+const CONTROL_PRG_RAM_ENABLE: u8 = 0x80;
+
+// mapper 85: VRC7a selects its second registers with A4, VRC7b with A3
+pub struct Vrc7 {
+    cartridge: Cartridge,
+    prg_banks: [u8; 3],
+    chr_banks: [u8; 8],
+    control: u8,
+    irq: vrc_irq::VrcIrq,
+    audio: opll::Opll,
+}
+
+pub fn new_vrc7(cartridge: Cartridge) -> Vrc7 {
+    let mirroring = if cartridge.mirroring == ppu::Mirroring::Horizontal { 1 } else { 0 };
+    return Vrc7 {
+        cartridge: cartridge,
+        prg_banks: [0, 1, 2],
+        chr_banks: [0; 8],
+        control: mirroring,
+        irq: vrc_irq::new_vrc_irq(),
+        audio: opll::new_opll(),
+    };
+}
+
+fn prg_bank_8k(vrc7: &Vrc7, addr: u16) -> usize {
+    let slot = ((addr >> 13) & 0x03) as usize;
+    if slot == 3 {
+        return (vrc7.cartridge.prg_rom.len() / 0x2000).max(1) - 1;
+    }
+    return (vrc7.prg_banks[slot] & 0x3F) as usize;
+}
+
+fn write_register(vrc7: &mut Vrc7, addr: u16, value: u8) {
+    // A3 on VRC7b boards, A4 on VRC7a
+    let odd = (addr & 0x18) != 0;
+    match (addr & 0xF000, odd) {
+        (0x8000, false) => vrc7.prg_banks[0] = value,
+        (0x8000, true) => vrc7.prg_banks[1] = value,
+        (0x9000, false) => vrc7.prg_banks[2] = value,
+        // $9010 selects a sound register, $9030 writes it
+        (0x9000, true) => {
+            if (addr & 0x20) != 0 {
+                opll::write_data(&mut vrc7.audio, value);
+            } else {
+                opll::write_address(&mut vrc7.audio, value);
+            }
+        }
+        (0xA000, _) | (0xB000, _) | (0xC000, _) | (0xD000, _) => {
+            let slot = (((addr - 0xA000) >> 12) * 2) as usize + if odd { 1 } else { 0 };
+            vrc7.chr_banks[slot] = value;
+        }
+        (0xE000, false) => {
+            vrc7.control = value;
+            if (value & CONTROL_AUDIO_RESET) != 0 {
+                opll::reset(&mut vrc7.audio);
+            }
+        }
+        (0xE000, true) => vrc_irq::write_latch(&mut vrc7.irq, value),
+        (_, false) => vrc_irq::write_control(&mut vrc7.irq, value),
+        (_, true) => vrc_irq::acknowledge(&mut vrc7.irq),
+    }
+}
+
+impl Mapper for Vrc7 {
+    fn read_prg(&mut self, addr: u16) -> Option<u8> {
+        if addr >= 0x8000 {
+            let bank = prg_bank_8k(self, addr);
+            return Some(read_prg_bank(&self.cartridge, 0x2000, bank, addr));
+        } else if addr >= 0x6000 && (self.control & CONTROL_PRG_RAM_ENABLE) != 0 {
+            let len = self.cartridge.prg_ram.len();
+            return Some(self.cartridge.prg_ram[(addr - 0x6000) as usize % len]);
+        }
+        return None;
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr >= 0x8000 {
+            write_register(self, addr, value);
+        } else if addr >= 0x6000 && (self.control & CONTROL_PRG_RAM_ENABLE) != 0 {
+            let len = self.cartridge.prg_ram.len();
+            self.cartridge.prg_ram[(addr - 0x6000) as usize % len] = value;
+        }
+    }
+
+    fn read_chr(&mut self, addr: u16) -> u8 {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        return read_chr_bank(&self.cartridge, 0x0400, bank, addr);
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let bank = self.chr_banks[(addr >> 10) as usize] as usize;
+        write_chr_bank(&mut self.cartridge, 0x0400, bank, addr, value);
+    }
+
+    fn mirroring(&self) -> ppu::Mirroring {
+        match self.control & CONTROL_MIRRORING {
+            0 => ppu::Mirroring::Vertical,
+            1 => ppu::Mirroring::Horizontal,
+            2 => ppu::Mirroring::SingleScreenLower,
+            _ => ppu::Mirroring::SingleScreenUpper,
+        }
+    }
+
+    fn clock_cpu(&mut self) {
+        vrc_irq::clock(&mut self.irq);
+        // held in reset, the sound core stays silent
+        if (self.control & CONTROL_AUDIO_RESET) == 0 {
+            opll::clock(&mut self.audio);
+        }
+    }
+
+    fn is_irq_asserted(&self) -> bool {
+        return vrc_irq::is_pending(&self.irq);
+    }
+
+    fn audio_output(&self) -> f32 {
+        return opll::output(&self.audio);
+    }
+}
@@ -0,0 +1,88 @@
+// IRQ control bits, the same on VRC4, VRC6 and VRC7
+const CONTROL_ENABLE_AFTER_ACK: u8 = 0x01;
+const CONTROL_ENABLE: u8 = 0x02;
+const CONTROL_CYCLE_MODE: u8 = 0x04;
+
+// in scanline mode the prescaler counts 341 PPU dots in steps of 3 per CPU cycle
+const PRESCALER_PERIOD: i16 = 341;
+
+// the Konami 8-bit counter, clocked by CPU cycles or by a scanline prescaler
+pub struct VrcIrq {
+    latch: u8,
+    counter: u8,
+    prescaler: i16,
+    enabled: bool,
+    enable_after_ack: bool,
+    cycle_mode: bool,
+    pending: bool,
+}
+
+pub fn new_vrc_irq() -> VrcIrq {
+    return VrcIrq {
+        latch: 0,
+        counter: 0,
+        prescaler: PRESCALER_PERIOD,
+        enabled: false,
+        enable_after_ack: false,
+        cycle_mode: false,
+        pending: false,
+    };
+}
+
+pub fn write_latch(irq: &mut VrcIrq, value: u8) {
+    irq.latch = value;
+}
+
+// VRC4 loads the latch one nibble at a time
+pub fn write_latch_low(irq: &mut VrcIrq, value: u8) {
+    irq.latch = (irq.latch & 0xF0) | (value & 0x0F);
+}
+
+pub fn write_latch_high(irq: &mut VrcIrq, value: u8) {
+    irq.latch = (irq.latch & 0x0F) | ((value & 0x0F) << 4);
+}
+
+pub fn write_control(irq: &mut VrcIrq, value: u8) {
+    irq.enable_after_ack = (value & CONTROL_ENABLE_AFTER_ACK) != 0;
+    irq.enabled = (value & CONTROL_ENABLE) != 0;
+    irq.cycle_mode = (value & CONTROL_CYCLE_MODE) != 0;
+    irq.pending = false;
+    if irq.enabled {
+        irq.counter = irq.latch;
+        irq.prescaler = PRESCALER_PERIOD;
+    }
+}
+
+pub fn acknowledge(irq: &mut VrcIrq) {
+    irq.pending = false;
+    irq.enabled = irq.enable_after_ack;
+}
+
+fn clock_counter(irq: &mut VrcIrq) {
+    if irq.counter == 0xFF {
+        irq.counter = irq.latch;
+        irq.pending = true;
+    } else {
+        irq.counter += 1;
+    }
+}
+
+// called once per CPU cycle
+pub fn clock(irq: &mut VrcIrq) {
+    if !irq.enabled {
+        return;
+    }
+    if irq.cycle_mode {
+        clock_counter(irq);
+        return;
+    }
+    irq.prescaler -= 3;
+    if irq.prescaler <= 0 {
+        irq.prescaler += PRESCALER_PERIOD;
+        clock_counter(irq);
+    }
+}
+
+pub fn is_pending(irq: &VrcIrq) -> bool {
+    return irq.pending;
+}
@@ -1,41 +1,190 @@
 mod palette;
 
+use super::mapper;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    FourScreen,
+}
+
 pub struct Ppu {
-    vram: Vec<u8>,
+    // pattern tables and nametable mapping live on the cartridge
+    mapper: mapper::SharedMapper,
+    // 2KB of console VRAM, extended to 4KB for four-screen boards
+    nametable: Vec<u8>,
+    palette: Vec<u8>,
     oam: Vec<u8>,
+    // internal scroll registers (loopy's v, t, x and w)
     vram_address: u16,
-    vram_write_counter: u8,
-    h_scroll: u8,
-    v_scroll: u8,
-    scroll_write_counter: u8,
+    temp_vram_address: u16,
+    fine_x: u8,
+    write_toggle: u8,
+    // $2007 reads return the previous contents of this buffer
+    read_buffer: u8,
     oam_address: u8,
     reg_controller: u8,
     reg_mask: u8,
     reg_status: u8,
-    cycle: u32,
+    scanline: u16,
+    dot: u16,
+    odd_frame: bool,
+    frame_ready: bool,
+    // writes to $2000/$2001/$2005/$2006 are ignored until the first pre-render line after reset
+    reset_latch: bool,
+    // background fetch latches and shift registers
+    nametable_latch: u8,
+    pattern_low_latch: u8,
+    pattern_high_latch: u8,
+    attribute_latch: u8,
+    pattern_low_shift: u16,
+    pattern_high_shift: u16,
+    attribute_low_shift: u16,
+    attribute_high_shift: u16,
+    // sprites selected for the next scanline, flips already applied
+    sprite_count: usize,
+    sprite_zero_on_line: bool,
+    // hardware draws at most 8 sprites per scanline; lifting it reduces flicker
+    sprite_limit: bool,
+    sprite_x: Vec<u8>,
+    sprite_attr: Vec<u8>,
+    sprite_pattern_low: Vec<u8>,
+    sprite_pattern_high: Vec<u8>,
+    frame: Vec<u8>,
+    palette_table: Vec<u8>,
 }
 
-pub fn new_ppu(rom_data: &Vec<u8>) -> Ppu {
-    let mut vram = vec![0; 0xFFFF];
-    for i in 0..rom_data.len() {
-        vram[i] = rom_data[i];
-    }
+pub fn new_ppu(mapper: mapper::SharedMapper) -> Ppu {
     return Ppu {
-        cycle: 0,
-        vram: vram,
+        mapper: mapper,
+        nametable: vec![0; 0x1000],
+        palette: vec![0; 0x20],
         oam: vec![0; 256],
-        vram_write_counter: 0,
-        scroll_write_counter: 0,
         vram_address: 0,
-        h_scroll: 0,
-        v_scroll: 0,
+        temp_vram_address: 0,
+        fine_x: 0,
+        write_toggle: 0,
+        read_buffer: 0,
         oam_address: 0,
         reg_controller: 0,
         reg_mask: 0,
         reg_status: 0,
+        scanline: 0,
+        dot: 0,
+        odd_frame: false,
+        frame_ready: false,
+        reset_latch: false,
+        nametable_latch: 0,
+        pattern_low_latch: 0,
+        pattern_high_latch: 0,
+        attribute_latch: 0,
+        pattern_low_shift: 0,
+        pattern_high_shift: 0,
+        attribute_low_shift: 0,
+        attribute_high_shift: 0,
+        sprite_count: 0,
+        sprite_zero_on_line: false,
+        sprite_limit: true,
+        sprite_x: vec![0; 64],
+        sprite_attr: vec![0; 64],
+        sprite_pattern_low: vec![0; 64],
+        sprite_pattern_high: vec![0; 64],
+        frame: vec![0; 256*240*3],
+        palette_table: palette::build_emphasis_table(),
     };
 }
 
+pub fn power_on(ppu: &mut Ppu) {
+    ppu.reg_status = 0;
+    ppu.oam_address = 0;
+    ppu.vram_address = 0;
+    reset(ppu);
+}
+
+pub fn reset(ppu: &mut Ppu) {
+    ppu.reg_controller = 0;
+    ppu.reg_mask = 0;
+    ppu.temp_vram_address = 0;
+    ppu.fine_x = 0;
+    ppu.write_toggle = 0;
+    ppu.read_buffer = 0;
+    ppu.scanline = 0;
+    ppu.dot = 0;
+    ppu.odd_frame = false;
+    ppu.reset_latch = true;
+}
+
+// index into console VRAM for a nametable address under the given mirroring
+pub fn nametable_index(mirroring: Mirroring, addr: u16) -> usize {
+    // $3000-$3EFF mirrors $2000-$2EFF, so only bits 10-11 pick the nametable
+    let offset = (addr & 0x03FF) as usize;
+    let nametable = ADDR_BG0 | (addr & 0x0C00);
+    let page = match mirroring {
+        Mirroring::Horizontal => if nametable == ADDR_BG0 || nametable == ADDR_BG1 { 0 } else { 1 },
+        Mirroring::Vertical => if nametable == ADDR_BG0 || nametable == ADDR_BG2 { 0 } else { 1 },
+        Mirroring::SingleScreenLower => 0,
+        Mirroring::SingleScreenUpper => 1,
+        Mirroring::FourScreen => {
+            match nametable {
+                ADDR_BG0 => 0,
+                ADDR_BG1 => 1,
+                ADDR_BG2 => 2,
+                _ => 3,
+            }
+        }
+    };
+    return page * 0x400 + offset;
+}
+
+fn palette_index(addr: u16) -> usize {
+    // $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries at $3F00/$3F04/$3F08/$3F0C
+    let index = addr & 0x1F;
+    if (index & 0x13) == 0x10 {
+        return (index & 0x0F) as usize;
+    }
+    return index as usize;
+}
+
+// boards watching the PPU address lines (A12 for MMC3) see every address put on the bus
+fn set_bus_address(ppu: &Ppu, addr: u16) {
+    ppu.mapper.borrow_mut().ppu_bus_address(addr & 0x3FFF);
+}
+
+// tells the cartridge which fetches follow and for which line (0 after the pre-render line)
+fn set_fetch_phase(ppu: &Ppu, phase: u8) {
+    let next_line = if ppu.scanline == SCANLINE_PRE_RENDER { 0 } else { ppu.scanline + 1 };
+    let tall_sprites = sprite_height(ppu) == 16;
+    ppu.mapper.borrow_mut().ppu_fetch_phase(phase, next_line, tall_sprites);
+}
+
+fn read_vram(ppu: &Ppu, addr: u16) -> u8 {
+    let addr = addr & 0x3FFF;
+    set_bus_address(ppu, addr);
+    if addr < 0x2000 {
+        return ppu.mapper.borrow_mut().read_chr(addr);
+    } else if addr < 0x3F00 {
+        // $3000-$3EFF mirrors $2000-$2EFF
+        return ppu.mapper.borrow_mut().read_nametable(addr & 0x2FFF, &ppu.nametable);
+    } else {
+        return ppu.palette[palette_index(addr)];
+    }
+}
+
+fn write_vram(ppu: &mut Ppu, addr: u16, value: u8) {
+    let addr = addr & 0x3FFF;
+    set_bus_address(ppu, addr);
+    if addr < 0x2000 {
+        ppu.mapper.borrow_mut().write_chr(addr, value);
+    } else if addr < 0x3F00 {
+        ppu.mapper.borrow_mut().write_nametable(addr & 0x2FFF, value, &mut ppu.nametable);
+    } else {
+        ppu.palette[palette_index(addr)] = value;
+    }
+}
+
 pub fn read_io(ppu: &mut Ppu, addr: u16) -> u8 {
     match addr {
         0x2000 => {
@@ -48,9 +197,7 @@ pub fn read_io(ppu: &mut Ppu, addr: u16) -> u8 {
             // ppu status
             let status = ppu.reg_status;
             ppu.reg_status = ppu.reg_status & 0x7F;
-            ppu.scroll_write_counter = 0;
-            ppu.vram_write_counter = 0;
-            ppu.reg_status = ppu.reg_status | 0x80;
+            ppu.write_toggle = 0;
             return status;
         }
         0x2003 => {
@@ -58,6 +205,12 @@ pub fn read_io(ppu: &mut Ppu, addr: u16) -> u8 {
         }
         0x2004 => {
             // oam access
+            let value = ppu.oam[ppu.oam_address as usize];
+            if (ppu.oam_address & 0x03) == 2 {
+                // unimplemented attribute bits read back as 0
+                return value & 0xE3;
+            }
+            return value;
         }
         0x2005 => {
             // scroll
@@ -67,7 +220,18 @@ pub fn read_io(ppu: &mut Ppu, addr: u16) -> u8 {
         }
         0x2007 => {
             // vram access
-            return ppu.vram[ppu.vram_address as usize];
+            let addr = ppu.vram_address & 0x3FFF;
+            let value;
+            if addr >= 0x3F00 {
+                // palette reads are immediate; the buffer gets the nametable byte underneath
+                value = read_vram(ppu, addr);
+                ppu.read_buffer = read_vram(ppu, addr - 0x1000);
+            } else {
+                value = ppu.read_buffer;
+                ppu.read_buffer = read_vram(ppu, addr);
+            }
+            increment_vram_address(ppu);
+            return value;
         }
         0x4014 => {
             // oam dma
@@ -79,10 +243,20 @@ pub fn read_io(ppu: &mut Ppu, addr: u16) -> u8 {
 }
 
 pub fn write_io(ppu: &mut Ppu, addr: u16, value: u8) {
+    if ppu.reset_latch {
+        match addr {
+            0x2000 | 0x2001 | 0x2005 | 0x2006 => {
+                return;
+            }
+            _ => {}
+        }
+    }
     match addr {
         0x2000 => {
             // ppu controller
             ppu.reg_controller = value;
+            // nametable select goes to t bits 10-11
+            ppu.temp_vram_address = (ppu.temp_vram_address & 0xF3FF) | (((value & 0x03) as u16) << 10);
         }
         0x2001 => {
             // ppu mask
@@ -99,34 +273,39 @@ pub fn write_io(ppu: &mut Ppu, addr: u16, value: u8) {
             // oam access
             ppu.oam[ppu.oam_address as usize] = value;
             // println!("oam address {:04X} = {:02X}", ppu.oam_address, value);
+            ppu.oam_address = ppu.oam_address.wrapping_add(1);
         }
         0x2005 => {
             // scroll
-            if ppu.scroll_write_counter == 0 {
-                ppu.h_scroll = value;
+            if ppu.write_toggle == 0 {
+                ppu.temp_vram_address = (ppu.temp_vram_address & 0xFFE0) | ((value >> 3) as u16);
+                ppu.fine_x = value & 0x07;
             } else {
-                ppu.v_scroll = value;
+                ppu.temp_vram_address = (ppu.temp_vram_address & 0x8C1F)
+                    | (((value & 0x07) as u16) << 12)
+                    | (((value >> 3) as u16) << 5);
             }
-            ppu.scroll_write_counter = ppu.scroll_write_counter ^ 1;
+            ppu.write_toggle = ppu.write_toggle ^ 1;
         }
         0x2006 => {
             // vram address
-            let shift: u8 = (1 - ppu.vram_write_counter) * 8;
-            if ppu.vram_write_counter == 0 {
-                ppu.vram_address = 0;
+            if ppu.write_toggle == 0 {
+                ppu.temp_vram_address = (ppu.temp_vram_address & 0x00FF) | (((value & 0x3F) as u16) << 8);
+            } else {
+                ppu.temp_vram_address = (ppu.temp_vram_address & 0xFF00) | (value as u16);
+                ppu.vram_address = ppu.temp_vram_address;
+                if !is_rendering_line(ppu) {
+                    // outside rendering the PPU keeps v on its address bus
+                    set_bus_address(ppu, ppu.vram_address);
+                }
             }
-            ppu.vram_address = ppu.vram_address | ((value as u16) << shift);
-            ppu.vram_write_counter = ppu.vram_write_counter ^ 1;
+            ppu.write_toggle = ppu.write_toggle ^ 1;
         }
         0x2007 => {
             // vram access
-            ppu.vram[ppu.vram_address as usize] = value;
+            write_vram(ppu, ppu.vram_address, value);
             // println!("vram address {:04X} = {:02X}", ppu.vram_address, value);
-            ppu.vram_address += 1;
-        }
-        0x4014 => {
-            // oam dma
-            println!("oam dma");
+            increment_vram_address(ppu);
         }
         _ => {
         }
@@ -139,6 +318,12 @@ const ADDR_BG0: u16 = 0x2000;
 const ADDR_BG1: u16 = 0x2400;
 const ADDR_BG2: u16 = 0x2800;
 const ADDR_BG3: u16 = 0x2C00;
+const ADDR_ATTRIBUTE: u16 = 0x23C0;
+
+const SCANLINE_VISIBLE_END: u16 = 240;
+const SCANLINE_VBLANK: u16 = 241;
+const SCANLINE_PRE_RENDER: u16 = 261;
+const DOTS_PER_SCANLINE: u16 = 341;
 
 #[inline(always)]
 fn put_pixel(canvas: &mut Vec<u8>, x: i32, y: i32, r: u8, g: u8, b: u8) {
@@ -157,71 +342,366 @@ fn put_pixel(canvas: &mut Vec<u8>, x: i32, y: i32, r: u8, g: u8, b: u8) {
 
 #[inline(always)]
 fn get_palette(ppu: &Ppu, palette_num: u8, offset: u8) -> (u8, u8, u8) {
-    let mut address = (0x3F00 + (offset as u16) + (palette_num as u16)) as usize;
+    let mut address = 0x3F00 + (offset as u16) + (palette_num as u16);
     if palette_num == 0 {
         address = 0x3F00;
     }
-    let palette_color = ((ppu.vram[address] & 0x3F) * 3) as usize;
-    return (palette::PALETTE_TABLE[palette_color], palette::PALETTE_TABLE[palette_color + 1], palette::PALETTE_TABLE[palette_color + 2]);
+    // palette RAM is inside the PPU, so lookups don't touch the address bus
+    return get_color(ppu, ppu.palette[palette_index(address)]);
 }
 
 #[inline(always)]
-fn put_tile(canvas: &mut Vec<u8>, ppu: &Ppu, base_x: i32, base_y: i32, base_addr: u16, chrnum: u8, palette_offset: u8) {
-    for y in 0..8 {
-        let addr = base_addr + ((chrnum as u16) * 16);
-        let palette_data_low = ppu.vram[(addr + 0 + y) as usize];
-        let palette_data_high = ppu.vram[(addr + 8 + y) as usize];
-        for x in 0..8 {
-            let palette_num = (
-                 ((palette_data_low  >> (7 - x)) & 1) |
-                (((palette_data_high >> (7 - x)) & 1) << 1)
-            ) as u8;
-            let (r, g, b) = get_palette(ppu, palette_num, palette_offset);
-            put_pixel(canvas, base_x + x, base_y + (y as i32), r, g, b);
-        }
+fn get_color(ppu: &Ppu, color: u8) -> (u8, u8, u8) {
+    let mut color = color & 0x3F;
+    if (ppu.reg_mask & 0x01) != 0 {
+        // greyscale keeps only the grey column of each row
+        color = color & 0x30;
     }
+    let emphasis = (ppu.reg_mask >> 5) as usize;
+    let index = (emphasis * 64 + color as usize) * 3;
+    return (ppu.palette_table[index], ppu.palette_table[index + 1], ppu.palette_table[index + 2]);
 }
 
 #[inline(always)]
-fn put_bg_tile(canvas: &mut Vec<u8>, ppu: &Ppu, base_x: i32, base_y: i32, chrnum: u8) {
-    let base_addr = (((ppu.reg_controller >> 4) & 1) as u16) * 0x1000;
-    // println!("put bg x:{} y:{} addr:{:04X}", base_x, base_y, base_addr);
-    put_tile(canvas, ppu, base_x, base_y, base_addr, chrnum, 0x00);
+fn is_rendering_enabled(ppu: &Ppu) -> bool {
+    return (ppu.reg_mask & 0x18) != 0;
 }
 
-pub fn run(ppu: &mut Ppu) {
-    if ppu.cycle >= 256 * 240 {
-        ppu.reg_status = ppu.reg_status | 0x80;
+// rendering enabled and on a line that fetches tiles
+fn is_rendering_line(ppu: &Ppu) -> bool {
+    return is_rendering_enabled(ppu) && (ppu.scanline < SCANLINE_VISIBLE_END || ppu.scanline == SCANLINE_PRE_RENDER);
+}
+
+fn increment_vram_address(ppu: &mut Ppu) {
+    if is_rendering_line(ppu) {
+        // while rendering, $2007 accesses bump coarse X and Y instead
+        increment_scroll_x(ppu);
+        increment_scroll_y(ppu);
+        return;
+    }
+    let increment = if (ppu.reg_controller & 0x04) != 0 { 32 } else { 1 };
+    ppu.vram_address = (ppu.vram_address + increment) & 0x3FFF;
+    set_bus_address(ppu, ppu.vram_address);
+}
+
+fn increment_scroll_x(ppu: &mut Ppu) {
+    if (ppu.vram_address & 0x001F) == 31 {
+        // wrap coarse X and switch horizontal nametable
+        ppu.vram_address = (ppu.vram_address & !0x001F) ^ 0x0400;
     } else {
-        ppu.reg_status = ppu.reg_status & 0x7F;
+        ppu.vram_address += 1;
     }
-    ppu.cycle += 1;
 }
 
-pub fn is_draw_timing(ppu: &Ppu) -> bool {
-    return ppu.cycle >= 341 * 262;
+fn increment_scroll_y(ppu: &mut Ppu) {
+    if (ppu.vram_address & 0x7000) != 0x7000 {
+        ppu.vram_address += 0x1000;
+        return;
+    }
+    ppu.vram_address = ppu.vram_address & !0x7000;
+    let mut coarse_y = (ppu.vram_address & 0x03E0) >> 5;
+    if coarse_y == 29 {
+        // last row of the nametable: switch vertical nametable
+        coarse_y = 0;
+        ppu.vram_address = ppu.vram_address ^ 0x0800;
+    } else if coarse_y == 31 {
+        // rows 30 and 31 hold attributes; wrap without switching
+        coarse_y = 0;
+    } else {
+        coarse_y += 1;
+    }
+    ppu.vram_address = (ppu.vram_address & !0x03E0) | (coarse_y << 5);
 }
 
-pub fn draw_to_canvas(canvas: &mut Vec<u8>, ppu: &mut Ppu) {
-    ppu.cycle = 0;
-    // println!("ppu controller:{:02X} mask:{:02X} status:{:02X}", ppu.reg_controller, ppu.reg_mask, ppu.reg_status);
-    for y in 0..(240/8) {
-        for x in 0..(256/8) {
-            let bgaddr = ADDR_BG0 + y * 32 + x;
-            let chrnum = ppu.vram[bgaddr as usize];
-            put_bg_tile(canvas, ppu, (x * 8) as i32, (y * 8) as i32, chrnum);
+fn transfer_address_x(ppu: &mut Ppu) {
+    ppu.vram_address = (ppu.vram_address & !0x041F) | (ppu.temp_vram_address & 0x041F);
+}
+
+fn transfer_address_y(ppu: &mut Ppu) {
+    ppu.vram_address = (ppu.vram_address & !0x7BE0) | (ppu.temp_vram_address & 0x7BE0);
+}
+
+fn load_background_shifters(ppu: &mut Ppu) {
+    ppu.pattern_low_shift = (ppu.pattern_low_shift & 0xFF00) | (ppu.pattern_low_latch as u16);
+    ppu.pattern_high_shift = (ppu.pattern_high_shift & 0xFF00) | (ppu.pattern_high_latch as u16);
+    ppu.attribute_low_shift = (ppu.attribute_low_shift & 0xFF00) | (if (ppu.attribute_latch & 1) != 0 { 0xFF } else { 0x00 });
+    ppu.attribute_high_shift = (ppu.attribute_high_shift & 0xFF00) | (if (ppu.attribute_latch & 2) != 0 { 0xFF } else { 0x00 });
+}
+
+fn update_background_shifters(ppu: &mut Ppu) {
+    if (ppu.reg_mask & 0x08) != 0 {
+        ppu.pattern_low_shift = ppu.pattern_low_shift << 1;
+        ppu.pattern_high_shift = ppu.pattern_high_shift << 1;
+        ppu.attribute_low_shift = ppu.attribute_low_shift << 1;
+        ppu.attribute_high_shift = ppu.attribute_high_shift << 1;
+    }
+}
+
+fn fetch_background(ppu: &mut Ppu) {
+    let base_addr = (((ppu.reg_controller >> 4) & 1) as u16) * 0x1000;
+    let fine_y = (ppu.vram_address >> 12) & 0x07;
+    match (ppu.dot - 1) % 8 {
+        0 => {
+            load_background_shifters(ppu);
+            ppu.nametable_latch = read_vram(ppu, ADDR_BG0 | (ppu.vram_address & 0x0FFF));
+        }
+        2 => {
+            // one attribute byte covers 32x32 pixels, two bits per 16x16 area
+            let v = ppu.vram_address;
+            let addr = ADDR_ATTRIBUTE | (v & 0x0C00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+            let mut attribute = read_vram(ppu, addr);
+            if (v & 0x0040) != 0 {
+                attribute = attribute >> 4;
+            }
+            if (v & 0x0002) != 0 {
+                attribute = attribute >> 2;
+            }
+            ppu.attribute_latch = attribute & 0x03;
+        }
+        4 => {
+            ppu.pattern_low_latch = read_vram(ppu, base_addr + (ppu.nametable_latch as u16) * 16 + fine_y);
+        }
+        6 => {
+            ppu.pattern_high_latch = read_vram(ppu, base_addr + (ppu.nametable_latch as u16) * 16 + fine_y + 8);
+        }
+        7 => {
+            increment_scroll_x(ppu);
+        }
+        _ => {
         }
     }
+}
 
-    for i in 0..(256/4) {
-        let base = i * 4;
-        let y = ppu.oam[base + 0];
-        let tile = ppu.oam[base + 1];
-        let attr = ppu.oam[base + 2];
-        let x = ppu.oam[base + 3];
+pub fn set_sprite_limit(ppu: &mut Ppu, enabled: bool) {
+    ppu.sprite_limit = enabled;
+}
 
+fn sprite_height(ppu: &Ppu) -> u16 {
+    return if (ppu.reg_controller & 0x20) != 0 { 16 } else { 8 };
+}
+
+fn is_sprite_in_range(ppu: &Ppu, y: u8) -> bool {
+    let y = y as u16;
+    return ppu.scanline >= y && ppu.scanline < y + sprite_height(ppu);
+}
+
+fn load_sprite(ppu: &mut Ppu, index: usize) {
+    let base = index * 4;
+    let y = ppu.oam[base + 0] as u16;
+    let tile = ppu.oam[base + 1];
+    let attr = ppu.oam[base + 2];
+    let x = ppu.oam[base + 3];
+    let height = sprite_height(ppu);
+
+    let mut row = ppu.scanline - y;
+    if (attr & 0x80) != 0 {
+        // vertical flip
+        row = height - 1 - row;
+    }
+    let addr = if height == 16 {
+        // 8x16 sprites pick their pattern table with bit 0 of the tile index
+        let base_addr = ((tile & 1) as u16) * 0x1000;
+        let top = (tile & 0xFE) as u16;
+        base_addr + (top + row / 8) * 16 + (row & 7)
+    } else {
         let base_addr = (((ppu.reg_controller >> 3) & 1) as u16) * 0x1000;
-        // println!("put bg x:{} y:{} addr:{:04X}", base_x, base_y, base_addr);
-        put_tile(canvas, ppu, x as i32, y as i32, base_addr, tile, 0x10);
+        base_addr + (tile as u16) * 16 + row
+    };
+
+    let mut low = read_vram(ppu, addr);
+    let mut high = read_vram(ppu, addr + 8);
+    if (attr & 0x40) != 0 {
+        // horizontal flip
+        low = low.reverse_bits();
+        high = high.reverse_bits();
+    }
+    let n = ppu.sprite_count;
+    ppu.sprite_x[n] = x;
+    ppu.sprite_attr[n] = attr;
+    ppu.sprite_pattern_low[n] = low;
+    ppu.sprite_pattern_high[n] = high;
+    ppu.sprite_count += 1;
+}
+
+// empty slots of the 8 sprite fetches still read tile $FF, which mappers can see on A12
+fn fetch_unused_sprite_slots(ppu: &mut Ppu) {
+    let base_addr = if sprite_height(ppu) == 16 {
+        ADDR_PATTERN1
+    } else {
+        (((ppu.reg_controller >> 3) & 1) as u16) * 0x1000
+    };
+    for _ in ppu.sprite_count..8 {
+        read_vram(ppu, base_addr + 0xFF * 16);
+        read_vram(ppu, base_addr + 0xFF * 16 + 8);
+    }
+}
+
+fn evaluate_sprites(ppu: &mut Ppu) {
+    // sprites found on this scanline are drawn on the next one, hence the one line Y offset
+    ppu.sprite_count = 0;
+    ppu.sprite_zero_on_line = false;
+    if ppu.scanline == SCANLINE_PRE_RENDER {
+        return;
+    }
+
+    let mut found = 0;
+    let mut overflow_start = 64;
+    for n in 0..64 {
+        if !is_sprite_in_range(ppu, ppu.oam[n * 4]) {
+            continue;
+        }
+        if found < 8 || !ppu.sprite_limit {
+            if n == 0 {
+                ppu.sprite_zero_on_line = true;
+            }
+            load_sprite(ppu, n);
+        }
+        found += 1;
+        if found == 8 {
+            overflow_start = n + 1;
+        }
+    }
+
+    // after 8 sprites the hardware also steps the byte offset m when a Y check fails,
+    // so it compares tile, attribute and X bytes as Y coordinates (the overflow bug)
+    let mut m = 0;
+    for n in overflow_start..64 {
+        if is_sprite_in_range(ppu, ppu.oam[n * 4 + m]) {
+            ppu.reg_status = ppu.reg_status | 0x20;
+            break;
+        }
+        m = (m + 1) & 3;
+    }
+}
+
+fn render_pixel(ppu: &mut Ppu) {
+    let x = (ppu.dot - 1) as i32;
+    let y = ppu.scanline as i32;
+
+    if !is_rendering_enabled(ppu) {
+        // with rendering off the backdrop is shown, or the colour v points at inside palette RAM
+        let addr = ppu.vram_address & 0x3FFF;
+        let (r, g, b) = if addr >= 0x3F00 { get_color(ppu, ppu.palette[palette_index(addr)]) } else { get_palette(ppu, 0, 0) };
+        put_pixel(&mut ppu.frame, x, y, r, g, b);
+        return;
+    }
+
+    // bits 1 and 2 of $2001 clip background and sprites in the leftmost 8 pixels
+    let show_background = (ppu.reg_mask & 0x08) != 0 && (x >= 8 || (ppu.reg_mask & 0x02) != 0);
+    let show_sprites = (ppu.reg_mask & 0x10) != 0 && (x >= 8 || (ppu.reg_mask & 0x04) != 0);
+
+    let mut palette_num = 0u8;
+    let mut palette_offset = 0u8;
+    if show_background {
+        let mux = 0x8000 >> ppu.fine_x;
+        palette_num = (if (ppu.pattern_low_shift & mux) != 0 { 1 } else { 0 })
+            | (if (ppu.pattern_high_shift & mux) != 0 { 2 } else { 0 });
+        palette_offset = ((if (ppu.attribute_low_shift & mux) != 0 { 1 } else { 0 })
+            | (if (ppu.attribute_high_shift & mux) != 0 { 2 } else { 0 })) << 2;
+    }
+
+    if show_sprites {
+        // the first opaque sprite in OAM order wins, even if it sits behind the background
+        for i in 0..ppu.sprite_count {
+            let column = x - (ppu.sprite_x[i] as i32);
+            if column < 0 || column >= 8 {
+                continue;
+            }
+            let sprite_palette_num = ((ppu.sprite_pattern_low[i] >> (7 - column)) & 1)
+                | (((ppu.sprite_pattern_high[i] >> (7 - column)) & 1) << 1);
+            if sprite_palette_num == 0 {
+                continue;
+            }
+            if i == 0 && ppu.sprite_zero_on_line && palette_num != 0 && x != 255 {
+                ppu.reg_status = ppu.reg_status | 0x40;
+            }
+            let attr = ppu.sprite_attr[i];
+            if (attr & 0x20) == 0 || palette_num == 0 {
+                palette_num = sprite_palette_num;
+                palette_offset = 0x10 | ((attr & 0x03) << 2);
+            }
+            break;
+        }
     }
-}
\ No newline at end of file
+
+    let (r, g, b) = get_palette(ppu, palette_num, palette_offset);
+    put_pixel(&mut ppu.frame, x, y, r, g, b);
+}
+
+pub fn run(ppu: &mut Ppu) {
+    let rendering = is_rendering_enabled(ppu);
+    let scanline = ppu.scanline;
+    let dot = ppu.dot;
+
+    if scanline < SCANLINE_VISIBLE_END || scanline == SCANLINE_PRE_RENDER {
+        if scanline == SCANLINE_PRE_RENDER && dot == 1 {
+            // vblank, sprite 0 hit and overflow are cleared at dot 1 of the pre-render line
+            ppu.reg_status = ppu.reg_status & 0x1F;
+            ppu.reset_latch = false;
+        }
+
+        if rendering {
+            if dot == 257 {
+                set_fetch_phase(ppu, mapper::PPU_FETCH_SPRITES);
+            }
+            if dot == 321 {
+                set_fetch_phase(ppu, mapper::PPU_FETCH_BACKGROUND);
+            }
+            if (dot >= 2 && dot <= 257) || (dot >= 321 && dot <= 337) {
+                update_background_shifters(ppu);
+                fetch_background(ppu);
+            }
+            if dot == 256 {
+                increment_scroll_y(ppu);
+            }
+            if dot == 257 {
+                load_background_shifters(ppu);
+                transfer_address_x(ppu);
+                evaluate_sprites(ppu);
+                fetch_unused_sprite_slots(ppu);
+            }
+            if scanline == SCANLINE_PRE_RENDER && dot >= 280 && dot <= 304 {
+                transfer_address_y(ppu);
+            }
+        }
+
+        if scanline < SCANLINE_VISIBLE_END && dot >= 1 && dot <= 256 {
+            render_pixel(ppu);
+        }
+    } else if scanline == SCANLINE_VBLANK && dot == 1 {
+        // vblank flag is set at dot 1 of scanline 241
+        ppu.reg_status = ppu.reg_status | 0x80;
+        ppu.frame_ready = true;
+        set_fetch_phase(ppu, mapper::PPU_FETCH_IDLE);
+    }
+
+    ppu.dot += 1;
+    if scanline == SCANLINE_PRE_RENDER && dot == 339 && ppu.odd_frame && rendering {
+        // odd frames skip the last dot of the pre-render line
+        ppu.dot += 1;
+    }
+    if ppu.dot >= DOTS_PER_SCANLINE {
+        ppu.dot = 0;
+        ppu.scanline += 1;
+        if ppu.scanline > SCANLINE_PRE_RENDER {
+            ppu.scanline = 0;
+            ppu.odd_frame = !ppu.odd_frame;
+        }
+    }
+}
+
+pub fn is_nmi_asserted(ppu: &Ppu) -> bool {
+    return (ppu.reg_status & 0x80) != 0 && (ppu.reg_controller & 0x80) != 0;
+}
+
+pub fn is_draw_timing(ppu: &Ppu) -> bool {
+    return ppu.frame_ready;
+}
+
+pub fn draw_to_canvas(canvas: &mut Vec<u8>, ppu: &mut Ppu) {
+    ppu.frame_ready = false;
+    for y in 0..240 {
+        let offset = y * 256 * 3;
+        canvas[offset..(offset + 256 * 3)].copy_from_slice(&ppu.frame[offset..(offset + 256 * 3)]);
+    }
+}
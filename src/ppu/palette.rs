@@ -63,4 +63,25 @@ pub const PALETTE_TABLE: [u8; 64*3] = [
     248,216,248,
     0,0,0,
     0,0,0,
-];
\ No newline at end of file
+];
+
+// each $2001 emphasis bit darkens the two other colour channels
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+// PALETTE_TABLE for all 8 combinations of the emphasis bits (bit 0 red, bit 1 green, bit 2 blue)
+pub fn build_emphasis_table() -> Vec<u8> {
+    let mut table = vec![0; 8 * 64 * 3];
+    for emphasis in 0..8 {
+        for i in 0..(64 * 3) {
+            let channel = i % 3;
+            let mut value = PALETTE_TABLE[i] as f32;
+            for bit in 0..3 {
+                if bit != channel && (emphasis & (1 << bit)) != 0 {
+                    value = value * EMPHASIS_ATTENUATION;
+                }
+            }
+            table[emphasis * 64 * 3 + i] = value as u8;
+        }
+    }
+    return table;
+}
@@ -3,6 +3,7 @@ use std::io::prelude::*;
 use std::path::Path;
 use std::str;
 use std::error::Error;
+use super::ppu;
 
 #[derive(Debug)]
 pub struct NesHeader {
@@ -31,6 +32,52 @@ pub struct NesRom {
 
 const NES_HEADER_SIZE: usize = 0x10;
 
+pub fn mirroring(header: &NesHeader) -> ppu::Mirroring {
+    if (header.flag6 & 0x08) != 0 {
+        return ppu::Mirroring::FourScreen;
+    }
+    if (header.flag6 & 0x01) != 0 {
+        return ppu::Mirroring::Vertical;
+    }
+    return ppu::Mirroring::Horizontal;
+}
+
+// NES 2.0 headers mark themselves with bits 2-3 of flag7 set to 10
+pub fn is_nes2(header: &NesHeader) -> bool {
+    return (header.flag7 & 0x0C) == 0x08;
+}
+
+// iNES mapper number: low nibble in flag6, high nibble in flag7; NES 2.0 adds bits 8-11 in flag8
+pub fn mapper_number(header: &NesHeader) -> u16 {
+    let mut number = ((header.flag7 & 0xF0) | (header.flag6 >> 4)) as u16;
+    if is_nes2(header) {
+        number = number | (((header.flag8 & 0x0F) as u16) << 8);
+    }
+    return number;
+}
+
+// board variant within a mapper number; 0 when the header doesn't say
+pub fn submapper(header: &NesHeader) -> u8 {
+    if is_nes2(header) {
+        return header.flag8 >> 4;
+    }
+    return 0;
+}
+
+// PRG RAM plus battery-backed PRG RAM, in bytes; 0 when the header doesn't say
+pub fn prg_ram_size(header: &NesHeader) -> usize {
+    if is_nes2(header) {
+        // shift counts of 64 bytes, 0 meaning none
+        let volatile = header.flag10 & 0x0F;
+        let battery = header.flag10 >> 4;
+        let volatile = if volatile == 0 { 0 } else { 64 << volatile };
+        let battery = if battery == 0 { 0 } else { 64 << battery };
+        return volatile + battery;
+    }
+    // iNES flag8 gives PRG RAM in 8KB units
+    return (header.flag8 as usize) * 0x2000;
+}
+
 fn load_program_rom(buffer: &[u8], header: &NesHeader) -> Result<ProgramRom, std::io::Error> {
     let start: usize = NES_HEADER_SIZE;
     let end = start + header.size_of_prg_rom as usize; 
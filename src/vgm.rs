@@ -0,0 +1,173 @@
+use std::fs::File;
+use std::io::prelude::*;
+
+// VGM timestamps are in samples at 44.1kHz
+const VGM_SAMPLE_RATE: f64 = 44100.0;
+const VGM_VERSION: u32 = 0x00000161;
+const VGM_HEADER_SIZE: usize = 0x100;
+const NES_APU_CLOCK: u32 = 1789772;
+
+const COMMAND_NES_APU_WRITE: u8 = 0xB4;
+const COMMAND_WAIT: u8 = 0x61;
+const COMMAND_WAIT_735: u8 = 0x62;
+const COMMAND_WAIT_882: u8 = 0x63;
+const COMMAND_DATA_BLOCK: u8 = 0x67;
+const COMMAND_END: u8 = 0x66;
+const DATA_BLOCK_NES_APU_RAM: u8 = 0xC2;
+
+// $4015 bit 4 starts a DPCM sample
+const STATUS_DMC_ENABLE: u8 = 0x10;
+// DPCM samples are read from $8000-$FFFF
+const SAMPLE_RAM_BASE: usize = 0x8000;
+const SAMPLE_RAM_SIZE: usize = 0x8000;
+
+pub struct ApuWrite {
+    pub cycle: u64,
+    pub addr: u16,
+    pub value: u8,
+}
+
+// DPCM bytes to load into the player's memory before writes[before_write]
+pub struct SampleBlock {
+    pub before_write: usize,
+    pub addr: u16,
+    pub data: Vec<u8>,
+}
+
+pub struct VgmLog {
+    pub writes: Vec<ApuWrite>,
+    pub sample_blocks: Vec<SampleBlock>,
+    // what the player's sample memory holds so far, so unchanged bytes aren't sent again
+    sample_ram: Vec<Option<u8>>,
+    // the write that started the current sample; its bytes have to be loaded before it
+    sample_start: usize,
+}
+
+pub fn new_vgm_log() -> VgmLog {
+    return VgmLog {
+        writes: Vec::new(),
+        sample_blocks: Vec::new(),
+        sample_ram: vec![None; SAMPLE_RAM_SIZE],
+        sample_start: 0,
+    };
+}
+
+pub fn log_write(log: &mut VgmLog, cycle: u64, addr: u16, value: u8) {
+    if addr == 0x4015 && (value & STATUS_DMC_ENABLE) != 0 {
+        log.sample_start = log.writes.len();
+    }
+    log.writes.push(ApuWrite {
+        cycle: cycle,
+        addr: addr,
+        value: value,
+    });
+}
+
+// records a byte the DMC read, which is what a bank switching board actually had mapped
+pub fn log_sample_fetch(log: &mut VgmLog, addr: u16, value: u8) {
+    let index = (addr as usize).wrapping_sub(SAMPLE_RAM_BASE) % SAMPLE_RAM_SIZE;
+    if log.sample_ram[index] == Some(value) {
+        return;
+    }
+    log.sample_ram[index] = Some(value);
+
+    // extend the last block when this byte follows it, else start a new one
+    let start = log.sample_start;
+    if let Some(block) = log.sample_blocks.last_mut() {
+        let next = block.addr as usize + block.data.len();
+        if block.before_write == start && next == addr as usize {
+            block.data.push(value);
+            return;
+        }
+    }
+    log.sample_blocks.push(SampleBlock {
+        before_write: start,
+        addr: addr,
+        data: vec![value],
+    });
+}
+
+fn push_sample_block(data: &mut Vec<u8>, block: &SampleBlock) {
+    data.push(COMMAND_DATA_BLOCK);
+    data.push(COMMAND_END);
+    data.push(DATA_BLOCK_NES_APU_RAM);
+    push_u32(data, (block.data.len() + 2) as u32);
+    data.push(block.addr as u8);
+    data.push((block.addr >> 8) as u8);
+    data.extend_from_slice(&block.data);
+}
+
+fn cycle_to_sample(cycle: u64, clock_rate: f64) -> u64 {
+    return ((cycle as f64) * VGM_SAMPLE_RATE / clock_rate) as u64;
+}
+
+fn push_u32(data: &mut Vec<u8>, value: u32) {
+    data.extend_from_slice(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8]);
+}
+
+fn write_u32(data: &mut Vec<u8>, offset: usize, value: u32) {
+    data[offset] = value as u8;
+    data[offset + 1] = (value >> 8) as u8;
+    data[offset + 2] = (value >> 16) as u8;
+    data[offset + 3] = (value >> 24) as u8;
+}
+
+fn push_wait(data: &mut Vec<u8>, samples: u64) {
+    let mut remain = samples;
+    while remain > 0 {
+        if remain == 735 {
+            data.push(COMMAND_WAIT_735);
+            remain = 0;
+        } else if remain == 882 {
+            data.push(COMMAND_WAIT_882);
+            remain = 0;
+        } else if remain <= 16 {
+            data.push(0x70 | ((remain - 1) as u8));
+            remain = 0;
+        } else {
+            let wait = if remain > 0xFFFF { 0xFFFF } else { remain };
+            data.push(COMMAND_WAIT);
+            data.push(wait as u8);
+            data.push((wait >> 8) as u8);
+            remain -= wait;
+        }
+    }
+}
+
+// end_cycle is the CPU cycle the capture stopped at
+pub fn build_vgm(log: &VgmLog, end_cycle: u64, clock_rate: f64) -> Vec<u8> {
+    let mut data = vec![0; VGM_HEADER_SIZE];
+    data[0..4].copy_from_slice(b"Vgm ");
+
+    let mut blocks = log.sample_blocks.iter().peekable();
+    let mut sample = 0;
+    for (index, write) in log.writes.iter().enumerate() {
+        while let Some(block) = blocks.next_if(|block| block.before_write <= index) {
+            push_sample_block(&mut data, block);
+        }
+        let write_sample = cycle_to_sample(write.cycle, clock_rate);
+        push_wait(&mut data, write_sample - sample);
+        sample = write_sample;
+        data.push(COMMAND_NES_APU_WRITE);
+        data.push((write.addr - 0x4000) as u8);
+        data.push(write.value);
+    }
+    let end_sample = cycle_to_sample(end_cycle, clock_rate).max(sample);
+    push_wait(&mut data, end_sample - sample);
+    data.push(COMMAND_END);
+
+    let length = data.len();
+    write_u32(&mut data, 0x04, (length - 0x04) as u32);
+    write_u32(&mut data, 0x08, VGM_VERSION);
+    write_u32(&mut data, 0x18, end_sample as u32);
+    write_u32(&mut data, 0x24, 60);
+    write_u32(&mut data, 0x34, (VGM_HEADER_SIZE - 0x34) as u32);
+    write_u32(&mut data, 0x84, NES_APU_CLOCK);
+    return data;
+}
+
+pub fn save_vgm(log: &VgmLog, end_cycle: u64, clock_rate: f64, filename: &str) -> Result<(), std::io::Error> {
+    let mut file = File::create(filename)?;
+    file.write_all(&build_vgm(log, end_cycle, clock_rate))?;
+    return Ok(());
+}